@@ -0,0 +1,245 @@
+use crate::hprof_parser::graph::{HeapGraph, StackFrame};
+use crate::hprof_parser::snapshot::SubTag;
+use crate::hprof_parser::threads::describe_frame;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Objects allocated from the same stack trace.
+#[derive(Debug, Clone)]
+pub struct AllocationSite<'a> {
+    pub stack_trace_serial_number: u32,
+    /// innermost frame first
+    pub frames: Vec<StackFrame<'a>>,
+    pub object_count: u64,
+    pub shallow_size: u64,
+}
+
+/// Objects whose allocation trace starts with the same frame, whatever the
+/// callers.
+#[derive(Debug, Clone)]
+pub struct FrameAllocations {
+    /// `com.foo.Bar.load(Bar.java:42)`
+    pub frame: String,
+    pub object_count: u64,
+    pub shallow_size: u64,
+}
+
+/// Objects and bytes per allocation stack trace and per top frame. Only
+/// meaningful for dumps taken with allocation tracking on, otherwise every
+/// object ends up untracked.
+#[derive(Debug, Clone)]
+pub struct Allocations<'a> {
+    /// largest shallow size first
+    pub sites: Vec<AllocationSite<'a>>,
+    /// largest shallow size first
+    pub top_frames: Vec<FrameAllocations>,
+    /// objects without a trace or with an empty one
+    pub untracked_count: u64,
+    pub untracked_size: u64,
+}
+
+impl<'a> Allocations<'a> {
+    /// Class objects are left out, their serial number is the one of the
+    /// trace that loaded them.
+    pub fn new(graph: &HeapGraph<'a>) -> Self {
+        let mut by_serial: HashMap<u32, (u64, u64)> = HashMap::new();
+        let mut untracked_count = 0;
+        let mut untracked_size = 0;
+        for subtag in graph.objects() {
+            if matches!(subtag, SubTag::ClassDump { .. }) {
+                continue;
+            }
+            let (Some(id), Some(serial)) = (subtag.object_id(), subtag.stack_trace_serial_number())
+            else {
+                continue;
+            };
+            let size = graph.shallow_size(id);
+            let traced = graph
+                .stack_trace(serial)
+                .is_some_and(|trace| !trace.frame_ids.is_empty());
+            if traced {
+                let (count, bytes) = by_serial.entry(serial).or_default();
+                *count += 1;
+                *bytes += size;
+            } else {
+                untracked_count += 1;
+                untracked_size += size;
+            }
+        }
+
+        let mut sites: Vec<AllocationSite<'a>> = by_serial
+            .into_iter()
+            .filter_map(|(serial, (object_count, shallow_size))| {
+                let trace = graph.stack_trace(serial)?;
+                Some(AllocationSite {
+                    stack_trace_serial_number: serial,
+                    frames: trace
+                        .frame_ids
+                        .iter()
+                        .filter_map(|id| graph.stack_frame(*id).copied())
+                        .collect(),
+                    object_count,
+                    shallow_size,
+                })
+            })
+            .collect();
+        sites.sort_by(|a, b| {
+            b.shallow_size.cmp(&a.shallow_size).then_with(|| {
+                a.stack_trace_serial_number
+                    .cmp(&b.stack_trace_serial_number)
+            })
+        });
+
+        let mut by_frame: HashMap<String, FrameAllocations> = HashMap::new();
+        for site in &sites {
+            let Some(top) = site.frames.first() else {
+                continue;
+            };
+            let frame = describe_frame(graph, top);
+            let allocations = by_frame
+                .entry(frame.clone())
+                .or_insert_with(|| FrameAllocations {
+                    frame,
+                    object_count: 0,
+                    shallow_size: 0,
+                });
+            allocations.object_count += site.object_count;
+            allocations.shallow_size += site.shallow_size;
+        }
+        let mut top_frames: Vec<FrameAllocations> = by_frame.into_values().collect();
+        top_frames.sort_by(|a, b| {
+            b.shallow_size
+                .cmp(&a.shallow_size)
+                .then_with(|| a.frame.cmp(&b.frame))
+        });
+
+        Self {
+            sites,
+            top_frames,
+            untracked_count,
+            untracked_size,
+        }
+    }
+
+    pub fn display<'r>(&'r self, graph: &'r HeapGraph<'a>) -> AllocationsDisplay<'r, 'a> {
+        AllocationsDisplay {
+            allocations: self,
+            graph,
+        }
+    }
+}
+
+pub struct AllocationsDisplay<'r, 'a> {
+    allocations: &'r Allocations<'a>,
+    graph: &'r HeapGraph<'a>,
+}
+
+impl<'r, 'a> Display for AllocationsDisplay<'r, 'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let allocations = self.allocations;
+        writeln!(f, "By top frame:")?;
+        for frame in &allocations.top_frames {
+            writeln!(
+                f,
+                "{:>10} {:>12}  {}",
+                frame.object_count, frame.shallow_size, frame.frame
+            )?;
+        }
+        writeln!(f, "By stack trace:")?;
+        for site in &allocations.sites {
+            writeln!(
+                f,
+                "{:>10} {:>12}  trace {}",
+                site.object_count, site.shallow_size, site.stack_trace_serial_number
+            )?;
+            for frame in &site.frames {
+                writeln!(f, "    at {}", describe_frame(self.graph, frame))?;
+            }
+        }
+        writeln!(
+            f,
+            "Untracked: {} objects, {} bytes",
+            allocations.untracked_count, allocations.untracked_size
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::{JavaType, JavaValue};
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    #[test]
+    fn sites_and_top_frames() {
+        let mut dump = DumpBuilder::new();
+        let class_id = dump.class("com.example.Loader", 0, &[("value", JavaType::Long)]);
+        let load = dump.stack_frame(class_id, "load", "Loader.java", 42);
+        let parse = dump.stack_frame(class_id, "parse", "Loader.java", 7);
+        let main = dump.stack_frame(class_id, "main", "Loader.java", 1);
+        let loaded = dump.stack_trace(1, &[load, parse]);
+        let loaded_from_main = dump.stack_trace(1, &[load, main]);
+        let parsed = dump.stack_trace(1, &[parse, main]);
+        let empty = dump.stack_trace(1, &[]);
+
+        dump.allocated_at(loaded);
+        let instances = [dump.instance(class_id, &[]), dump.instance(class_id, &[])];
+        dump.allocated_at(loaded_from_main);
+        let array = dump.primitive_array(JavaType::Byte, &[JavaValue::Byte(0); 100]);
+        dump.allocated_at(parsed);
+        let parsed_instance = dump.instance(class_id, &[]);
+        dump.allocated_at(empty);
+        let untraced = dump.instance(class_id, &[]);
+        dump.allocated_at(0);
+        dump.instance(class_id, &[]);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let instance_size = graph.shallow_size(instances[0]);
+        let array_size = graph.shallow_size(array);
+        assert!(array_size > 2 * instance_size);
+
+        let allocations = Allocations::new(&graph);
+        let sites: Vec<(u32, usize, u64, u64)> = allocations
+            .sites
+            .iter()
+            .map(|site| {
+                (
+                    site.stack_trace_serial_number,
+                    site.frames.len(),
+                    site.object_count,
+                    site.shallow_size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            sites,
+            vec![
+                (loaded_from_main, 2, 1, array_size),
+                (loaded, 2, 2, 2 * instance_size),
+                (parsed, 2, 1, graph.shallow_size(parsed_instance)),
+            ]
+        );
+        assert_eq!(allocations.sites[1].frames[1].method_name, "parse");
+
+        let top_frames: Vec<(&str, u64, u64)> = allocations
+            .top_frames
+            .iter()
+            .map(|frame| (frame.frame.as_str(), frame.object_count, frame.shallow_size))
+            .collect();
+        assert_eq!(
+            top_frames,
+            vec![
+                (
+                    "com.example.Loader.load(Loader.java:42)",
+                    3,
+                    array_size + 2 * instance_size
+                ),
+                ("com.example.Loader.parse(Loader.java:7)", 1, instance_size),
+            ]
+        );
+
+        // the empty trace and no trace at all, class objects are left out
+        assert_eq!(allocations.untracked_count, 2);
+        assert_eq!(allocations.untracked_size, 2 * graph.shallow_size(untraced));
+    }
+}
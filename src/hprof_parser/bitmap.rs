@@ -0,0 +1,587 @@
+use crate::hprof_parser::dominator::DominatorTree;
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::path::{PathFinder, ReferencePath};
+use crate::hprof_parser::snapshot::{JavaValue, SubTag};
+use crate::Result;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MANIFEST: &str = "manifest.tsv";
+
+/// `Bitmap.Config`, the layout of the pixels in memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BitmapConfig {
+    Alpha8,
+    Rgb565,
+    Argb8888,
+    RgbaF16,
+}
+
+impl BitmapConfig {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            BitmapConfig::Alpha8 => 1,
+            BitmapConfig::Rgb565 => 2,
+            BitmapConfig::Argb8888 => 4,
+            BitmapConfig::RgbaF16 => 8,
+        }
+    }
+}
+
+impl Display for BitmapConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BitmapConfig::Alpha8 => "ALPHA_8",
+            BitmapConfig::Rgb565 => "RGB_565",
+            BitmapConfig::Argb8888 => "ARGB_8888",
+            BitmapConfig::RgbaF16 => "RGBA_F16",
+        })
+    }
+}
+
+/// `Bitmap.CompressFormat` used by `Bitmap.dumpData` to encode the pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ImageFormat::Jpeg => "JPEG",
+            ImageFormat::Png => "PNG",
+            ImageFormat::Webp => "WEBP",
+        })
+    }
+}
+
+/// An `android.graphics.Bitmap` whose pixels are in the dump.
+#[derive(Debug, Clone)]
+pub struct Bitmap {
+    pub object_id: u64,
+    pub width: u32,
+    pub height: u32,
+    /// the Java object does not hold the config: it is inferred from the size
+    /// of `mBuffer`, read from the header of PNG data and otherwise taken to
+    /// be the default ARGB_8888
+    pub config: BitmapConfig,
+    /// `None` when `pixels` holds the raw pixels
+    pub encoding: Option<ImageFormat>,
+    pub pixels: Vec<u8>,
+}
+
+impl Bitmap {
+    /// 64 bit FNV-1a hash of the pixel data, stable across runs.
+    pub fn content_hash(&self) -> u64 {
+        self.pixels
+            .iter()
+            .fold(0xcbf29ce484222325u64, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    /// Bytes the pixels take in memory, whatever the size of the dumped data.
+    pub fn pixel_bytes(&self) -> u64 {
+        self.width as u64 * self.height as u64 * self.config.bytes_per_pixel() as u64
+    }
+
+    /// Raw pixels as non premultiplied RGBA, 4 bytes per pixel.
+    /// `None` for encoded pixels or when the buffer is too small.
+    pub fn to_rgba(&self) -> Option<Vec<u8>> {
+        if self.encoding.is_some() {
+            return None;
+        }
+        let pixels = self.width as usize * self.height as usize;
+        let bytes_per_pixel = self.config.bytes_per_pixel() as usize;
+        if pixels == 0 || self.pixels.len() < pixels * bytes_per_pixel {
+            return None;
+        }
+        // a reused buffer can be larger than the bitmap, rows are not padded
+        let stride = self.width as usize * bytes_per_pixel;
+
+        let mut rgba = Vec::with_capacity(pixels * 4);
+        for row in self.pixels.chunks(stride).take(self.height as usize) {
+            for pixel in row.chunks(bytes_per_pixel).take(self.width as usize) {
+                match self.config {
+                    BitmapConfig::Alpha8 => rgba.extend_from_slice(&[0, 0, 0, pixel[0]]),
+                    BitmapConfig::Rgb565 => {
+                        let v = u16::from_le_bytes([pixel[0], pixel[1]]);
+                        let r = (v >> 11) & 0x1f;
+                        let g = (v >> 5) & 0x3f;
+                        let b = v & 0x1f;
+                        rgba.extend_from_slice(&[
+                            (r * 255 / 31) as u8,
+                            (g * 255 / 63) as u8,
+                            (b * 255 / 31) as u8,
+                            255,
+                        ]);
+                    }
+                    // skia N32 is RGBA in memory on android, premultiplied
+                    BitmapConfig::Argb8888 => {
+                        rgba.extend_from_slice(&unpremultiply([
+                            pixel[0], pixel[1], pixel[2], pixel[3],
+                        ]));
+                    }
+                    BitmapConfig::RgbaF16 => {
+                        let mut c = [0u8; 4];
+                        for (i, half) in pixel.chunks(2).enumerate() {
+                            let v = f16_to_f32(u16::from_le_bytes([half[0], half[1]]));
+                            c[i] = (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+                        }
+                        rgba.extend_from_slice(&unpremultiply(c));
+                    }
+                }
+            }
+        }
+        Some(rgba)
+    }
+
+    /// Writes raw pixels as a PNG. Data `Bitmap.dumpData` already encoded is
+    /// written unchanged, so JPEG and WEBP stay as they are, there is no
+    /// decoder for them here. Returns the format of the file, `None` without
+    /// writing anything when the pixels cannot be decoded.
+    pub fn write_image<P: AsRef<Path>>(&self, path: P) -> Result<Option<ImageFormat>> {
+        match self.encoding {
+            Some(format) => {
+                fs::write(path, &self.pixels)?;
+                Ok(Some(format))
+            }
+            None => {
+                let Some(rgba) = self.to_rgba() else {
+                    return Ok(None);
+                };
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder = png::Encoder::new(file, self.width, self.height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.write_header()?.write_image_data(&rgba)?;
+                Ok(Some(ImageFormat::Png))
+            }
+        }
+    }
+
+    /// Extension of the file [`Self::write_image`] writes.
+    pub fn file_extension(&self) -> &'static str {
+        match self.encoding {
+            Some(ImageFormat::Jpeg) => "jpg",
+            Some(ImageFormat::Webp) => "webp",
+            _ => "png",
+        }
+    }
+}
+
+/// A bitmap written by [`export_bitmaps`].
+#[derive(Debug, Clone)]
+pub struct ExportedBitmap {
+    pub object_id: u64,
+    pub file_name: String,
+    pub format: ImageFormat,
+}
+
+/// Writes every bitmap with pixel data into `dir`, one image per bitmap,
+/// plus a `manifest.tsv` mapping object ids to file names and formats.
+/// Raw pixels are written as PNG, encoded data keeps its format.
+pub fn export_bitmaps<P: AsRef<Path>>(graph: &HeapGraph, dir: P) -> Result<Vec<ExportedBitmap>> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let mut exported = Vec::new();
+    let mut manifest = BufWriter::new(File::create(dir.join(MANIFEST))?);
+    writeln!(manifest, "object_id\twidth\theight\tconfig\tformat\tfile")?;
+    for bitmap in bitmaps(graph) {
+        let file_name = format!("bitmap_{:#x}.{}", bitmap.object_id, bitmap.file_extension());
+        let Some(format) = bitmap.write_image(dir.join(&file_name))? else {
+            continue;
+        };
+        writeln!(
+            manifest,
+            "{:#x}\t{}\t{}\t{}\t{}\t{}",
+            bitmap.object_id, bitmap.width, bitmap.height, bitmap.config, format, file_name
+        )?;
+        exported.push(ExportedBitmap {
+            object_id: bitmap.object_id,
+            file_name,
+            format,
+        });
+    }
+    manifest.flush()?;
+    Ok(exported)
+}
+
+/// Bitmaps with pixel data, either from `mBuffer` (ART before Android 8) or
+/// from the buffers `Bitmap.dumpData` collects right before the heap is dumped.
+pub fn bitmaps(graph: &HeapGraph) -> Vec<Bitmap> {
+    let dump_data = dump_data_buffers(graph);
+
+    let mut bitmaps: Vec<Bitmap> = graph
+        .instances_of("android.graphics.Bitmap")
+        .into_iter()
+        .filter_map(|id| {
+            let width = int_field(graph, id, "mWidth")?;
+            let height = int_field(graph, id, "mHeight")?;
+
+            let buffer = match graph.field(id, "mBuffer") {
+                Some(JavaValue::Object(buffer)) if buffer != 0 => graph.byte_array(buffer),
+                _ => None,
+            };
+            let (config, encoding, pixels) = match buffer {
+                Some(pixels) => (buffer_config(width, height, pixels.len())?, None, pixels),
+                None => {
+                    let Some(JavaValue::Long(native_ptr)) = graph.field(id, "mNativePtr") else {
+                        return None;
+                    };
+                    let (format, buffer) = dump_data.get(&native_ptr)?;
+                    let pixels = graph.byte_array(*buffer)?;
+                    (encoded_config(*format, &pixels), Some(*format), pixels)
+                }
+            };
+
+            Some(Bitmap {
+                object_id: id,
+                width,
+                height,
+                config,
+                encoding,
+                pixels,
+            })
+        })
+        .collect();
+    bitmaps.sort_by_key(|bitmap| bitmap.object_id);
+    bitmaps
+}
+
+/// One copy of a duplicated bitmap.
+#[derive(Debug, Clone)]
+pub struct BitmapCopy<'a> {
+    pub object_id: u64,
+    pub retained_size: u64,
+    /// `None` when the bitmap is not strongly reachable
+    pub path: Option<ReferencePath<'a>>,
+}
+
+/// Bitmaps whose pixels are identical.
+#[derive(Debug, Clone)]
+pub struct DuplicateBitmap<'a> {
+    pub content_hash: u64,
+    pub width: u32,
+    pub height: u32,
+    pub config: BitmapConfig,
+    /// in memory size of one copy, see [`Bitmap::pixel_bytes`]
+    pub pixel_bytes: u64,
+    pub copies: Vec<BitmapCopy<'a>>,
+}
+
+impl<'a> DuplicateBitmap<'a> {
+    /// What sharing a single copy would save.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.pixel_bytes * (self.copies.len() as u64 - 1)
+    }
+}
+
+/// Groups bitmaps with identical pixel data, sorted by wasted bytes, largest
+/// first. Bitmaps sharing a content hash are compared byte for byte.
+pub fn find_duplicate_bitmaps<'a>(
+    graph: &HeapGraph<'a>,
+    dominators: &DominatorTree,
+) -> Vec<DuplicateBitmap<'a>> {
+    let mut by_hash: HashMap<(u64, u32, u32), Vec<Vec<Bitmap>>> = HashMap::new();
+    for bitmap in bitmaps(graph) {
+        let groups = by_hash
+            .entry((bitmap.content_hash(), bitmap.width, bitmap.height))
+            .or_default();
+        match groups
+            .iter_mut()
+            .find(|group| group[0].pixels == bitmap.pixels)
+        {
+            Some(group) => group.push(bitmap),
+            None => groups.push(vec![bitmap]),
+        }
+    }
+    let groups: Vec<((u64, u32, u32), Vec<Bitmap>)> = by_hash
+        .into_iter()
+        .flat_map(|(key, groups)| groups.into_iter().map(move |group| (key, group)))
+        .filter(|(_, bitmaps)| bitmaps.len() > 1)
+        .collect();
+
+    let targets: Vec<u64> = groups
+        .iter()
+        .flat_map(|(_, bitmaps)| bitmaps)
+        .map(|bitmap| bitmap.object_id)
+        .collect();
+    let mut paths = PathFinder::new(graph).shortest_paths(&targets);
+
+    let mut duplicates: Vec<DuplicateBitmap<'a>> = groups
+        .into_iter()
+        .map(|((content_hash, width, height), bitmaps)| DuplicateBitmap {
+            content_hash,
+            width,
+            height,
+            config: bitmaps[0].config,
+            pixel_bytes: bitmaps[0].pixel_bytes(),
+            copies: bitmaps
+                .iter()
+                .map(|bitmap| BitmapCopy {
+                    object_id: bitmap.object_id,
+                    retained_size: dominators.retained_size(bitmap.object_id),
+                    path: paths.remove(&bitmap.object_id),
+                })
+                .collect(),
+        })
+        .collect();
+    duplicates.sort_by(|a, b| {
+        b.wasted_bytes()
+            .cmp(&a.wasted_bytes())
+            .then_with(|| a.content_hash.cmp(&b.content_hash))
+    });
+    duplicates
+}
+
+/// `mNativePtr` -> (format, encoded `byte[]`) from `Bitmap.dumpData`.
+fn dump_data_buffers(graph: &HeapGraph) -> HashMap<i64, (ImageFormat, u64)> {
+    let mut buffers = HashMap::new();
+    for class_id in graph.class_ids("android.graphics.Bitmap") {
+        let Some(dump_data) = graph
+            .static_fields(*class_id)
+            .into_iter()
+            .find(|field| field.name == "dumpData")
+            .and_then(|field| match field.value {
+                JavaValue::Object(id) if id != 0 => Some(id),
+                _ => None,
+            })
+        else {
+            continue;
+        };
+
+        // Bitmap.CompressFormat ordinal
+        let format = match int_field(graph, dump_data, "format") {
+            Some(0) => ImageFormat::Jpeg,
+            Some(1) => ImageFormat::Png,
+            Some(2..=4) => ImageFormat::Webp,
+            _ => continue,
+        };
+        let count = int_field(graph, dump_data, "count").unwrap_or(u32::MAX) as usize;
+        let (Some(JavaValue::Object(natives)), Some(JavaValue::Object(array))) = (
+            graph.field(dump_data, "natives"),
+            graph.field(dump_data, "buffers"),
+        ) else {
+            continue;
+        };
+        let (
+            Some(SubTag::PrimitiveArrayDump {
+                elements: natives, ..
+            }),
+            Some(SubTag::ObjectArrayDump {
+                elements: array, ..
+            }),
+        ) = (graph.object(natives), graph.object(array))
+        else {
+            continue;
+        };
+
+        for (native, buffer) in natives.iter().zip(array).take(count) {
+            if let (JavaValue::Long(native), true) = (native, *buffer != 0) {
+                buffers.insert(*native, (format, *buffer));
+            }
+        }
+    }
+    buffers
+}
+
+/// 16 bit PNGs come from RGBA_F16 bitmaps, anything else is assumed to be ARGB_8888.
+fn encoded_config(format: ImageFormat, data: &[u8]) -> BitmapConfig {
+    // signature, IHDR length and type, width, height, then the bit depth
+    const BIT_DEPTH: usize = 24;
+    match (format, data.get(BIT_DEPTH)) {
+        (ImageFormat::Png, Some(16)) if data[12..16] == *b"IHDR" => BitmapConfig::RgbaF16,
+        _ => BitmapConfig::Argb8888,
+    }
+}
+
+/// Config whose pixel size makes `length` bytes exactly, `None` when no
+/// config does.
+fn buffer_config(width: u32, height: u32, length: usize) -> Option<BitmapConfig> {
+    let pixels = width as usize * height as usize;
+    if pixels == 0 || !length.is_multiple_of(pixels) {
+        return None;
+    }
+    match length / pixels {
+        1 => Some(BitmapConfig::Alpha8),
+        2 => Some(BitmapConfig::Rgb565),
+        4 => Some(BitmapConfig::Argb8888),
+        8 => Some(BitmapConfig::RgbaF16),
+        _ => None,
+    }
+}
+
+fn int_field(graph: &HeapGraph, id: u64, name: &str) -> Option<u32> {
+    match graph.field(id, name)? {
+        JavaValue::Int(value) => Some(value.max(0) as u32),
+        _ => None,
+    }
+}
+
+fn unpremultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    if a == 0 || a == 255 {
+        return [r, g, b, a];
+    }
+    let scale = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+    [scale(r), scale(g), scale(b), a]
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f => sign * f32::INFINITY,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::JavaType;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    /// `android.graphics.Bitmap` instances with their pixels in `mBuffer`.
+    fn buffer_bitmaps(bitmaps: &[(i32, i32, &[u8])]) -> (Vec<u8>, Vec<u64>) {
+        let mut dump = DumpBuilder::new();
+        let fields = [
+            ("mWidth", JavaType::Int),
+            ("mHeight", JavaType::Int),
+            ("mBuffer", JavaType::Object),
+        ];
+        let class_id = dump.class("android.graphics.Bitmap", 0, &fields);
+        let ids = bitmaps
+            .iter()
+            .map(|(width, height, pixels)| {
+                let values: Vec<JavaValue> =
+                    pixels.iter().map(|b| JavaValue::Byte(*b as i8)).collect();
+                let buffer = dump.primitive_array(JavaType::Byte, &values);
+                dump.instance(
+                    class_id,
+                    &[
+                        ("mWidth", JavaValue::Int(*width)),
+                        ("mHeight", JavaValue::Int(*height)),
+                        ("mBuffer", JavaValue::Object(buffer)),
+                    ],
+                )
+            })
+            .collect();
+        (dump.build(), ids)
+    }
+
+    #[test]
+    fn buffer_bitmap_configs() {
+        let (bytes, ids) = buffer_bitmaps(&[
+            (2, 2, &[0; 16]),
+            (2, 2, &[0; 8]),
+            (2, 2, &[0; 4]),
+            (2, 2, &[0; 7]),
+        ]);
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let bitmaps = bitmaps(&graph);
+        let configs: Vec<(u64, BitmapConfig)> = bitmaps
+            .iter()
+            .map(|bitmap| (bitmap.object_id, bitmap.config))
+            .collect();
+        assert_eq!(
+            configs,
+            vec![
+                (ids[0], BitmapConfig::Argb8888),
+                (ids[1], BitmapConfig::Rgb565),
+                (ids[2], BitmapConfig::Alpha8),
+            ]
+        );
+        assert!(bitmaps.iter().all(|bitmap| bitmap.encoding.is_none()));
+        assert_eq!(bitmaps[1].pixel_bytes(), 8);
+    }
+
+    #[test]
+    fn export_rgb565() {
+        // red, green, blue and white, little endian
+        let pixels = [0x00, 0xf8, 0xe0, 0x07, 0x1f, 0x00, 0xff, 0xff];
+        let (bytes, ids) = buffer_bitmaps(&[(2, 2, &pixels)]);
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let dir = std::env::temp_dir().join(format!("bitmaps_{}", std::process::id()));
+
+        let exported = export_bitmaps(&graph, &dir).unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].object_id, ids[0]);
+        assert_eq!(exported[0].format, ImageFormat::Png);
+        let manifest = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+        assert!(manifest.contains("\t2\t2\tRGB_565\tPNG\t"));
+
+        let decoder = png::Decoder::new(File::open(dir.join(&exported[0].file_name)).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(
+            image[..info.buffer_size()],
+            [255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn alpha8_to_rgba() {
+        let bitmap = Bitmap {
+            object_id: 1,
+            width: 2,
+            height: 1,
+            config: BitmapConfig::Alpha8,
+            encoding: None,
+            pixels: vec![0x80, 0xff],
+        };
+        assert_eq!(
+            bitmap.to_rgba().unwrap(),
+            vec![0, 0, 0, 0x80, 0, 0, 0, 0xff]
+        );
+    }
+
+    #[test]
+    fn buffer_configs() {
+        assert_eq!(buffer_config(3, 2, 6), Some(BitmapConfig::Alpha8));
+        assert_eq!(buffer_config(3, 2, 12), Some(BitmapConfig::Rgb565));
+        assert_eq!(buffer_config(3, 2, 24), Some(BitmapConfig::Argb8888));
+        assert_eq!(buffer_config(3, 2, 48), Some(BitmapConfig::RgbaF16));
+        assert_eq!(buffer_config(3, 2, 25), None);
+        assert_eq!(buffer_config(3, 2, 18), None);
+        assert_eq!(buffer_config(0, 2, 0), None);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3800), 0.5);
+        assert_eq!(f16_to_f32(0x3555), 0.333_251_95);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        // smallest subnormal
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn unpremultiplied_colors() {
+        assert_eq!(unpremultiply([10, 20, 30, 255]), [10, 20, 30, 255]);
+        assert_eq!(unpremultiply([0, 0, 0, 0]), [0, 0, 0, 0]);
+        assert_eq!(unpremultiply([64, 32, 0, 128]), [128, 64, 0, 128]);
+        // rounded to the nearest value
+        assert_eq!(unpremultiply([1, 2, 3, 3]), [85, 170, 255, 3]);
+        // out of range premultiplied values are clamped
+        assert_eq!(unpremultiply([200, 0, 0, 100]), [255, 0, 0, 100]);
+    }
+}
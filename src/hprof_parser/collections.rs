@@ -0,0 +1,1098 @@
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::path::describe_referrer;
+use crate::hprof_parser::snapshot::{JavaValue, SubTag};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// Capacity over size ratio from which a collection counts as oversized.
+const OVERSIZED_RATIO: usize = 4;
+/// Unused slots below which an oversized collection is not worth reporting.
+const MIN_UNUSED_SLOTS: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CollectionKind {
+    ArrayList,
+    ArrayDeque,
+    HashMap,
+    LinkedHashMap,
+    ConcurrentHashMap,
+    ArrayMap,
+    ArraySet,
+    SparseArray,
+    LongSparseArray,
+}
+
+impl CollectionKind {
+    pub fn is_map(&self) -> bool {
+        !matches!(
+            self,
+            CollectionKind::ArrayList | CollectionKind::ArrayDeque | CollectionKind::ArraySet
+        )
+    }
+}
+
+impl Display for CollectionKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Classes decoded by [`collection`], subclasses first so that e.g. a
+/// `LinkedHashMap` is not read as a plain `HashMap`.
+const COLLECTION_CLASSES: [(&str, CollectionKind); 15] = [
+    ("java.util.ArrayList", CollectionKind::ArrayList),
+    ("java.util.ArrayDeque", CollectionKind::ArrayDeque),
+    ("java.util.LinkedHashMap", CollectionKind::LinkedHashMap),
+    ("java.util.HashMap", CollectionKind::HashMap),
+    (
+        "java.util.concurrent.ConcurrentHashMap",
+        CollectionKind::ConcurrentHashMap,
+    ),
+    ("android.util.ArrayMap", CollectionKind::ArrayMap),
+    (
+        "androidx.collection.SimpleArrayMap",
+        CollectionKind::ArrayMap,
+    ),
+    (
+        "android.support.v4.util.SimpleArrayMap",
+        CollectionKind::ArrayMap,
+    ),
+    ("android.util.ArraySet", CollectionKind::ArraySet),
+    ("androidx.collection.ArraySet", CollectionKind::ArraySet),
+    ("android.util.SparseArray", CollectionKind::SparseArray),
+    (
+        "androidx.collection.SparseArrayCompat",
+        CollectionKind::SparseArray,
+    ),
+    (
+        "android.util.LongSparseArray",
+        CollectionKind::LongSparseArray,
+    ),
+    (
+        "androidx.collection.LongSparseArrayCompat",
+        CollectionKind::LongSparseArray,
+    ),
+    (
+        "androidx.collection.LongSparseArray",
+        CollectionKind::LongSparseArray,
+    ),
+];
+
+/// Logical content of a collection instance.
+#[derive(Debug, Clone)]
+pub struct Collection {
+    pub object_id: u64,
+    pub kind: CollectionKind,
+    /// number of elements or entries actually found
+    pub size: usize,
+    /// length of the backing array, 0 when it has not been allocated yet
+    pub capacity: usize,
+    /// elements of lists, deques and sets, 0 for null
+    pub elements: Vec<u64>,
+    /// entries of maps in iteration order; keys of sparse arrays are
+    /// `JavaValue::Int` or `JavaValue::Long`, values are 0 for null
+    pub entries: Vec<(JavaValue, u64)>,
+    /// arrays sized by the capacity, e.g. `elementData` or `mHashes` and `mArray`
+    pub backing_arrays: Vec<u64>,
+}
+
+impl Collection {
+    /// Every object the collection holds: elements, keys and values.
+    pub fn referenced_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        let keys = self.entries.iter().filter_map(|(key, _)| match key {
+            JavaValue::Object(id) => Some(*id),
+            _ => None,
+        });
+        let values = self.entries.iter().map(|(_, value)| *value);
+        self.elements
+            .iter()
+            .copied()
+            .chain(keys)
+            .chain(values)
+            .filter(|id| *id != 0)
+    }
+}
+
+/// Kind of collection `id` is an instance of, if it is a supported one.
+pub fn collection_kind(graph: &HeapGraph, id: u64) -> Option<CollectionKind> {
+    let class_id = graph.class_of(id)?;
+    COLLECTION_CLASSES
+        .iter()
+        .find(|(name, _)| graph.is_subclass_of(class_id, name))
+        .map(|(_, kind)| *kind)
+}
+
+/// Decodes a supported collection, `None` for any other object. Field names
+/// of the JDK, of older Android releases and of AndroidX are all tried.
+pub fn collection(graph: &HeapGraph, id: u64) -> Option<Collection> {
+    let kind = collection_kind(graph, id)?;
+    let mut collection = Collection {
+        object_id: id,
+        kind,
+        size: 0,
+        capacity: 0,
+        elements: Vec::new(),
+        entries: Vec::new(),
+        backing_arrays: Vec::new(),
+    };
+    match kind {
+        CollectionKind::ArrayList => {
+            let data_id = object_field(graph, id, &["elementData", "array"]);
+            let size = int_field(graph, id, &["size"]).unwrap_or(0);
+            let data = object_array(graph, data_id);
+            collection.backing_arrays.push(data_id);
+            collection.capacity = data.len();
+            collection.elements = data.iter().take(size).copied().collect();
+        }
+        CollectionKind::ArrayDeque => {
+            let data_id = object_field(graph, id, &["elements"]);
+            let data = object_array(graph, data_id);
+            collection.backing_arrays.push(data_id);
+            collection.capacity = data.len();
+            let head = int_field(graph, id, &["head"]).unwrap_or(0);
+            let tail = int_field(graph, id, &["tail"]).unwrap_or(0);
+            if !data.is_empty() {
+                let mut index = head % data.len();
+                // elements are never null, the first null slot ends the deque
+                while index != tail && data[index] != 0 {
+                    collection.elements.push(data[index]);
+                    index = (index + 1) % data.len();
+                    if collection.elements.len() == data.len() {
+                        break;
+                    }
+                }
+            }
+        }
+        CollectionKind::HashMap | CollectionKind::LinkedHashMap => {
+            let table_id = object_field(graph, id, &["table"]);
+            let table = object_array(graph, table_id);
+            collection.backing_arrays.push(table_id);
+            collection.capacity = table.len();
+            // Android before 7 keeps the null key out of the table
+            let null_key = object_field(graph, id, &["entryForNullKey"]);
+            if null_key != 0 {
+                collection
+                    .entries
+                    .push((JavaValue::Object(0), node_value(graph, null_key)));
+            }
+            let linked_head = match kind {
+                CollectionKind::LinkedHashMap => linked_head(graph, id),
+                _ => None,
+            };
+            match linked_head {
+                Some((head, next_field, end)) => collection.entries.extend(
+                    chain(graph, head, next_field)
+                        .take_while(|node| *node != end)
+                        .map(|node| map_entry(graph, node)),
+                ),
+                None => collection.entries.extend(
+                    table
+                        .iter()
+                        .flat_map(|bucket| chain(graph, *bucket, "next"))
+                        .map(|node| map_entry(graph, node)),
+                ),
+            }
+        }
+        CollectionKind::ConcurrentHashMap => {
+            let table_id = object_field(graph, id, &["table"]);
+            let table = object_array(graph, table_id);
+            collection.backing_arrays.push(table_id);
+            collection.capacity = table.len();
+            for bucket in &table {
+                // trees are kept behind a TreeBin whose `first` links every node
+                let first = match graph.field(*bucket, "first") {
+                    Some(JavaValue::Object(first)) => first,
+                    _ => *bucket,
+                };
+                collection.entries.extend(
+                    chain(graph, first, "next")
+                        // forwarding nodes of a resize in progress hold no entry
+                        .filter(|node| graph.field(*node, "nextTable").is_none())
+                        .map(|node| map_entry(graph, node)),
+                );
+            }
+        }
+        CollectionKind::ArrayMap | CollectionKind::ArraySet => {
+            let hashes = object_field(graph, id, &["mHashes", "hashes"]);
+            collection.capacity = match graph.object(hashes) {
+                Some(SubTag::PrimitiveArrayDump { elements, .. }) => elements.len(),
+                _ => 0,
+            };
+            let array_id = object_field(graph, id, &["mArray", "array"]);
+            let array = object_array(graph, array_id);
+            collection.backing_arrays.extend([hashes, array_id]);
+            let size = int_field(graph, id, &["mSize", "_size", "size"]).unwrap_or(0);
+            if kind == CollectionKind::ArrayMap {
+                collection.entries = array
+                    .chunks_exact(2)
+                    .take(size)
+                    .map(|pair| (JavaValue::Object(pair[0]), pair[1]))
+                    .collect();
+            } else {
+                collection.elements = array.iter().take(size).copied().collect();
+            }
+        }
+        CollectionKind::SparseArray | CollectionKind::LongSparseArray => {
+            let keys_id = object_field(graph, id, &["mKeys", "keys"]);
+            let keys = match graph.object(keys_id) {
+                Some(SubTag::PrimitiveArrayDump { elements, .. }) => elements.as_slice(),
+                _ => &[],
+            };
+            let values_id = object_field(graph, id, &["mValues", "values"]);
+            let values = object_array(graph, values_id);
+            collection.backing_arrays.extend([keys_id, values_id]);
+            collection.capacity = values.len();
+            let size = int_field(graph, id, &["mSize", "size"]).unwrap_or(0);
+            // removed values are replaced by the shared DELETED sentinel until gc()
+            let deleted = deleted_sentinels(graph, id);
+            collection.entries = keys
+                .iter()
+                .zip(values.iter())
+                .take(size)
+                .filter(|(_, value)| !deleted.contains(value))
+                .map(|(key, value)| (*key, *value))
+                .collect();
+        }
+    }
+    collection.size = match kind.is_map() {
+        true => collection.entries.len(),
+        false => collection.elements.len(),
+    };
+    collection.backing_arrays.retain(|id| *id != 0);
+    Some(collection)
+}
+
+/// Non null elements of an ArrayList, or of any other supported list or set.
+pub fn list_elements(graph: &HeapGraph, id: u64) -> Vec<u64> {
+    match collection(graph, id) {
+        Some(collection) => collection
+            .elements
+            .into_iter()
+            .filter(|id| *id != 0)
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CollectionWaste {
+    /// allocated backing arrays but no element
+    Empty,
+    /// a single element, a singleton collection would do
+    SingleElement,
+    /// capacity much larger than the size
+    Oversized,
+}
+
+impl Display for CollectionWaste {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollectionWaste::Empty => write!(f, "empty"),
+            CollectionWaste::SingleElement => write!(f, "single element"),
+            CollectionWaste::Oversized => write!(f, "oversized"),
+        }
+    }
+}
+
+/// Wasteful collections of one kind held by the same field.
+#[derive(Debug, Clone)]
+pub struct WastefulCollections {
+    /// `com.foo.Model.tags`, or the kind of gc root holding the collections
+    pub holder: String,
+    pub kind: CollectionKind,
+    pub waste: CollectionWaste,
+    pub collection_ids: Vec<u64>,
+    /// bytes of the backing arrays that are not needed, for a single element
+    /// or oversized collection the share of its unused slots
+    pub wasted_bytes: u64,
+}
+
+impl Display for WastefulCollections {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let count = self.collection_ids.len();
+        write!(
+            f,
+            "{}: {} {} {}{} = {}",
+            self.holder,
+            grouped(count as u64),
+            self.waste,
+            self.kind,
+            if count == 1 { "" } else { "s" },
+            format_bytes(self.wasted_bytes)
+        )
+    }
+}
+
+/// `12000` -> `12,000`
+fn grouped(n: u64) -> String {
+    let digits = n.to_string();
+    let mut text = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            text.push(',');
+        }
+        text.push(digit);
+    }
+    text
+}
+
+/// Bytes below 1 KB, whole KB below 1 MB and MB with one decimal above.
+fn format_bytes(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    match bytes {
+        bytes if bytes < KB => format!("{} bytes", bytes),
+        bytes if bytes < MB => format!("{} KB", grouped((bytes + KB / 2) / KB)),
+        bytes => format!("{:.1} MB", bytes as f64 / MB as f64),
+    }
+}
+
+/// Empty but allocated, single element and oversized collections, grouped by
+/// the field holding them and sorted by wasted bytes, largest first.
+pub fn find_wasteful_collections(graph: &HeapGraph) -> Vec<WastefulCollections> {
+    let shared_arrays = static_arrays(graph);
+    let mut kinds: HashMap<u64, Option<CollectionKind>> = HashMap::new();
+    let mut wasteful: Vec<(Collection, CollectionWaste, u64)> = Vec::new();
+    for subtag in graph.objects() {
+        let SubTag::InstanceDump {
+            object_id,
+            class_object_id,
+            ..
+        } = subtag
+        else {
+            continue;
+        };
+        let kind = *kinds
+            .entry(*class_object_id)
+            .or_insert_with(|| collection_kind(graph, *object_id));
+        if kind.is_none() {
+            continue;
+        }
+        let Some(collection) = collection(graph, *object_id) else {
+            continue;
+        };
+        // arrays shared through a static field, such as the EMPTY_TABLE of
+        // HashMap before Android 7, are not the collection's to waste
+        let backing_arrays: Vec<u64> = collection
+            .backing_arrays
+            .iter()
+            .copied()
+            .filter(|id| !shared_arrays.contains(id))
+            .collect();
+        if collection.capacity == 0 || backing_arrays.is_empty() {
+            continue;
+        }
+        let backing_bytes: u64 = backing_arrays
+            .iter()
+            .map(|id| graph.shallow_size(*id))
+            .sum();
+        let unused = collection.capacity.saturating_sub(collection.size);
+        let (waste, wasted_bytes) = match collection.size {
+            0 => (CollectionWaste::Empty, backing_bytes),
+            // only the slots beyond the one in use
+            1 => (
+                CollectionWaste::SingleElement,
+                backing_bytes * unused as u64 / collection.capacity as u64,
+            ),
+            size if collection.capacity >= size * OVERSIZED_RATIO && unused >= MIN_UNUSED_SLOTS => {
+                (
+                    CollectionWaste::Oversized,
+                    backing_bytes * unused as u64 / collection.capacity as u64,
+                )
+            }
+            _ => continue,
+        };
+        wasteful.push((collection, waste, wasted_bytes));
+    }
+
+    let ids: HashSet<u64> = wasteful.iter().map(|(c, _, _)| c.object_id).collect();
+    let referrers = graph.referrers(&ids);
+    let roots: HashMap<u64, &str> = graph
+        .gc_roots()
+        .filter_map(|root| Some((root.object_id()?, root.root_name()?)))
+        .collect();
+
+    let mut groups: HashMap<(String, CollectionKind, CollectionWaste), WastefulCollections> =
+        HashMap::new();
+    for (collection, waste, wasted_bytes) in wasteful {
+        // a collection is nearly always owned by a single field, the first
+        // referrer is used so that bytes are only counted once
+        let holder = match referrers
+            .get(&collection.object_id)
+            .and_then(|referrers| referrers.iter().min_by_key(|(from, _)| *from))
+        {
+            Some((from, edge)) => describe_referrer(graph, *from, *edge),
+            None => roots
+                .get(&collection.object_id)
+                .copied()
+                .unwrap_or("unreferenced")
+                .to_string(),
+        };
+        let group = groups
+            .entry((holder.clone(), collection.kind, waste))
+            .or_insert_with(|| WastefulCollections {
+                holder,
+                kind: collection.kind,
+                waste,
+                collection_ids: Vec::new(),
+                wasted_bytes: 0,
+            });
+        group.collection_ids.push(collection.object_id);
+        group.wasted_bytes += wasted_bytes;
+    }
+
+    let mut groups: Vec<WastefulCollections> = groups.into_values().collect();
+    for group in &mut groups {
+        group.collection_ids.sort_unstable();
+    }
+    groups.sort_by(|a, b| {
+        b.wasted_bytes
+            .cmp(&a.wasted_bytes)
+            .then_with(|| a.holder.cmp(&b.holder))
+    });
+    groups
+}
+
+/// Arrays referenced by a static field of any class.
+fn static_arrays(graph: &HeapGraph) -> HashSet<u64> {
+    graph
+        .objects()
+        .filter_map(|subtag| match subtag {
+            SubTag::ClassDump {
+                class_object_id, ..
+            } => Some(*class_object_id),
+            _ => None,
+        })
+        .flat_map(|class_id| graph.static_fields(class_id))
+        .filter_map(|field| match field.value {
+            JavaValue::Object(id) if id != 0 => Some(id),
+            _ => None,
+        })
+        .filter(|id| {
+            matches!(
+                graph.object(*id),
+                Some(SubTag::ObjectArrayDump { .. } | SubTag::PrimitiveArrayDump { .. })
+            )
+        })
+        .collect()
+}
+
+/// First object field among `names` the instance has, 0 if none or null.
+fn object_field(graph: &HeapGraph, id: u64, names: &[&str]) -> u64 {
+    names
+        .iter()
+        .find_map(|name| match graph.field(id, name) {
+            Some(JavaValue::Object(value)) => Some(value),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+fn int_field(graph: &HeapGraph, id: u64, names: &[&str]) -> Option<usize> {
+    names.iter().find_map(|name| match graph.field(id, name) {
+        Some(JavaValue::Int(value)) => Some(value.max(0) as usize),
+        _ => None,
+    })
+}
+
+fn object_array(graph: &HeapGraph, id: u64) -> Vec<u64> {
+    match graph.object(id) {
+        Some(SubTag::ObjectArrayDump { elements, .. }) => elements.clone(),
+        _ => Vec::new(),
+    }
+}
+
+/// Nodes linked through `next_field`, starting at `node`. Stops on cycles,
+/// which only a dump taken in the middle of a resize could contain.
+fn chain<'g>(
+    graph: &'g HeapGraph,
+    node: u64,
+    next_field: &'g str,
+) -> impl Iterator<Item = u64> + 'g {
+    let mut seen = HashSet::new();
+    std::iter::successors(Some(node), move |node| {
+        Some(object_field(graph, *node, &[next_field]))
+    })
+    .take_while(move |node| *node != 0 && seen.insert(*node))
+}
+
+fn map_entry(graph: &HeapGraph, node: u64) -> (JavaValue, u64) {
+    (
+        JavaValue::Object(object_field(graph, node, &["key"])),
+        node_value(graph, node),
+    )
+}
+
+fn node_value(graph: &HeapGraph, node: u64) -> u64 {
+    object_field(graph, node, &["value", "val"])
+}
+
+/// First entry of a LinkedHashMap, the field linking to the next one and the
+/// node ending the list: `head` and `after` in the JDK, a circular list
+/// around a `header` sentinel linked by `nxt` on Android before 7.
+fn linked_head(graph: &HeapGraph, id: u64) -> Option<(u64, &'static str, u64)> {
+    if let Some(JavaValue::Object(head)) = graph.field(id, "head") {
+        return Some((head, "after", 0));
+    }
+    let header = object_field(graph, id, &["header"]);
+    if header == 0 {
+        return None;
+    }
+    Some((object_field(graph, header, &["nxt"]), "nxt", header))
+}
+
+/// The `DELETED` markers of a sparse array class and its superclasses.
+fn deleted_sentinels(graph: &HeapGraph, id: u64) -> HashSet<u64> {
+    let Some(class_id) = graph.class_of(id) else {
+        return HashSet::new();
+    };
+    graph
+        .class_hierarchy(class_id)
+        .flat_map(|class_id| graph.static_fields(class_id))
+        .filter(|field| field.name == "DELETED")
+        .filter_map(|field| match field.value {
+            JavaValue::Object(sentinel) if sentinel != 0 => Some(sentinel),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::JavaType;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    fn objects(dump: &mut DumpBuilder, count: usize) -> Vec<u64> {
+        let class_id = dump.class("com.example.Item", 0, &[]);
+        (0..count).map(|_| dump.instance(class_id, &[])).collect()
+    }
+
+    fn object(id: u64) -> JavaValue {
+        JavaValue::Object(id)
+    }
+
+    fn entries(pairs: &[(u64, u64)]) -> Vec<(JavaValue, u64)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (object(*key), *value))
+            .collect()
+    }
+
+    #[test]
+    fn lists() {
+        let mut dump = DumpBuilder::new();
+        let items = objects(&mut dump, 3);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let list_class = dump.class(
+            "java.util.ArrayList",
+            0,
+            &[("elementData", JavaType::Object), ("size", JavaType::Int)],
+        );
+        let data = dump.object_array(array_class, &[items[0], items[1], 0, 0]);
+        let list = dump.instance(
+            list_class,
+            &[("elementData", object(data)), ("size", JavaValue::Int(2))],
+        );
+        let deque_class = dump.class(
+            "java.util.ArrayDeque",
+            0,
+            &[
+                ("elements", JavaType::Object),
+                ("head", JavaType::Int),
+                ("tail", JavaType::Int),
+            ],
+        );
+        // wrapped around the end of the array
+        let elements = dump.object_array(array_class, &[items[2], 0, items[0], items[1]]);
+        let deque = dump.instance(
+            deque_class,
+            &[
+                ("elements", object(elements)),
+                ("head", JavaValue::Int(2)),
+                ("tail", JavaValue::Int(1)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let decoded = collection(&graph, list).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::ArrayList);
+        assert_eq!((decoded.size, decoded.capacity), (2, 4));
+        assert_eq!(decoded.elements, vec![items[0], items[1]]);
+        assert_eq!(decoded.backing_arrays, vec![data]);
+        assert_eq!(list_elements(&graph, list), vec![items[0], items[1]]);
+
+        let decoded = collection(&graph, deque).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::ArrayDeque);
+        assert_eq!((decoded.size, decoded.capacity), (3, 4));
+        assert_eq!(decoded.elements, vec![items[0], items[1], items[2]]);
+
+        assert!(collection(&graph, items[0]).is_none());
+        assert!(list_elements(&graph, items[0]).is_empty());
+    }
+
+    #[test]
+    fn jdk_hash_maps() {
+        let mut dump = DumpBuilder::new();
+        let items = objects(&mut dump, 8);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let map_class = dump.class("java.util.HashMap", 0, &[("table", JavaType::Object)]);
+        let linked_class = dump.class(
+            "java.util.LinkedHashMap",
+            map_class,
+            &[("head", JavaType::Object)],
+        );
+        let node_class = dump.class(
+            "java.util.HashMap$Node",
+            0,
+            &[
+                ("key", JavaType::Object),
+                ("value", JavaType::Object),
+                ("next", JavaType::Object),
+            ],
+        );
+        let entry_class = dump.class(
+            "java.util.LinkedHashMap$Entry",
+            node_class,
+            &[("after", JavaType::Object)],
+        );
+
+        let chained = dump.instance(
+            node_class,
+            &[("key", object(items[4])), ("value", object(items[5]))],
+        );
+        let first = dump.instance(
+            node_class,
+            &[
+                ("key", object(items[0])),
+                ("value", object(items[1])),
+                ("next", object(chained)),
+            ],
+        );
+        let second = dump.instance(
+            node_class,
+            &[("key", object(items[2])), ("value", object(items[3]))],
+        );
+        let table = dump.object_array(array_class, &[first, 0, second, 0]);
+        let map = dump.instance(map_class, &[("table", object(table))]);
+
+        let last = dump.instance(
+            entry_class,
+            &[("key", object(items[6])), ("value", object(items[7]))],
+        );
+        let head = dump.instance(
+            entry_class,
+            &[
+                ("key", object(items[4])),
+                ("value", object(items[5])),
+                ("after", object(last)),
+            ],
+        );
+        let table = dump.object_array(array_class, &[last, head]);
+        let linked = dump.instance(
+            linked_class,
+            &[("table", object(table)), ("head", object(head))],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let decoded = collection(&graph, map).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::HashMap);
+        assert_eq!((decoded.size, decoded.capacity), (3, 4));
+        assert_eq!(
+            decoded.entries,
+            entries(&[
+                (items[0], items[1]),
+                (items[4], items[5]),
+                (items[2], items[3])
+            ])
+        );
+        let mut referenced: Vec<u64> = decoded.referenced_ids().collect();
+        referenced.sort_unstable();
+        assert_eq!(referenced, items[..6].to_vec());
+
+        // in insertion order rather than table order
+        let decoded = collection(&graph, linked).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::LinkedHashMap);
+        assert_eq!(
+            decoded.entries,
+            entries(&[(items[4], items[5]), (items[6], items[7])])
+        );
+    }
+
+    /// Android before 7: the null key out of the table and a circular list
+    /// around a `header` sentinel.
+    #[test]
+    fn android_hash_maps() {
+        let mut dump = DumpBuilder::new();
+        let items = objects(&mut dump, 5);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let map_class = dump.class(
+            "java.util.HashMap",
+            0,
+            &[
+                ("table", JavaType::Object),
+                ("entryForNullKey", JavaType::Object),
+            ],
+        );
+        let linked_class = dump.class(
+            "java.util.LinkedHashMap",
+            map_class,
+            &[("header", JavaType::Object)],
+        );
+        let entry_class = dump.class(
+            "java.util.HashMap$HashMapEntry",
+            0,
+            &[
+                ("key", JavaType::Object),
+                ("value", JavaType::Object),
+                ("next", JavaType::Object),
+            ],
+        );
+        let linked_entry_class = dump.class(
+            "java.util.LinkedHashMap$LinkedEntry",
+            entry_class,
+            &[("nxt", JavaType::Object)],
+        );
+
+        let null_key = dump.instance(entry_class, &[("value", object(items[0]))]);
+        let header = dump.new_id();
+        let second = dump.instance(
+            linked_entry_class,
+            &[
+                ("key", object(items[3])),
+                ("value", object(items[4])),
+                ("nxt", object(header)),
+            ],
+        );
+        let first = dump.instance(
+            linked_entry_class,
+            &[
+                ("key", object(items[1])),
+                ("value", object(items[2])),
+                ("nxt", object(second)),
+            ],
+        );
+        dump.instance_with_id(header, linked_entry_class, &[("nxt", object(first))]);
+        let table = dump.object_array(array_class, &[second, first]);
+        let linked = dump.instance(
+            linked_class,
+            &[
+                ("table", object(table)),
+                ("entryForNullKey", object(null_key)),
+                ("header", object(header)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let decoded = collection(&graph, linked).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::LinkedHashMap);
+        assert_eq!(decoded.size, 3);
+        assert_eq!(
+            decoded.entries,
+            entries(&[(0, items[0]), (items[1], items[2]), (items[3], items[4])])
+        );
+    }
+
+    #[test]
+    fn concurrent_hash_map() {
+        let mut dump = DumpBuilder::new();
+        let items = objects(&mut dump, 6);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let map_class = dump.class(
+            "java.util.concurrent.ConcurrentHashMap",
+            0,
+            &[("table", JavaType::Object)],
+        );
+        let node_class = dump.class(
+            "java.util.concurrent.ConcurrentHashMap$Node",
+            0,
+            &[
+                ("key", JavaType::Object),
+                ("val", JavaType::Object),
+                ("next", JavaType::Object),
+            ],
+        );
+        let bin_class = dump.class(
+            "java.util.concurrent.ConcurrentHashMap$TreeBin",
+            0,
+            &[("first", JavaType::Object)],
+        );
+        let forwarding_class = dump.class(
+            "java.util.concurrent.ConcurrentHashMap$ForwardingNode",
+            0,
+            &[("nextTable", JavaType::Object)],
+        );
+
+        let node = dump.instance(
+            node_class,
+            &[("key", object(items[0])), ("val", object(items[1]))],
+        );
+        let tree_second = dump.instance(
+            node_class,
+            &[("key", object(items[4])), ("val", object(items[5]))],
+        );
+        let tree_first = dump.instance(
+            node_class,
+            &[
+                ("key", object(items[2])),
+                ("val", object(items[3])),
+                ("next", object(tree_second)),
+            ],
+        );
+        let bin = dump.instance(bin_class, &[("first", object(tree_first))]);
+        let forwarding = dump.instance(forwarding_class, &[]);
+        let table = dump.object_array(array_class, &[node, bin, forwarding, 0]);
+        let map = dump.instance(map_class, &[("table", object(table))]);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let decoded = collection(&graph, map).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::ConcurrentHashMap);
+        assert_eq!((decoded.size, decoded.capacity), (3, 4));
+        assert_eq!(
+            decoded.entries,
+            entries(&[
+                (items[0], items[1]),
+                (items[2], items[3]),
+                (items[4], items[5])
+            ])
+        );
+    }
+
+    #[test]
+    fn array_maps_and_sets() {
+        let mut dump = DumpBuilder::new();
+        let items = objects(&mut dump, 4);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let fields = [
+            ("mHashes", JavaType::Object),
+            ("mArray", JavaType::Object),
+            ("mSize", JavaType::Int),
+        ];
+        let map_class = dump.class("android.util.ArrayMap", 0, &fields);
+        let set_class = dump.class("androidx.collection.ArraySet", 0, &fields);
+
+        let hashes = dump.primitive_array(JavaType::Int, &[JavaValue::Int(0); 4]);
+        let array = dump.object_array(
+            array_class,
+            &[items[0], items[1], items[2], items[3], 0, 0, 0, 0],
+        );
+        let map = dump.instance(
+            map_class,
+            &[
+                ("mHashes", object(hashes)),
+                ("mArray", object(array)),
+                ("mSize", JavaValue::Int(2)),
+            ],
+        );
+        let set_hashes = dump.primitive_array(JavaType::Int, &[JavaValue::Int(0); 4]);
+        let set_array = dump.object_array(array_class, &[items[0], items[1], items[2], 0]);
+        let set = dump.instance(
+            set_class,
+            &[
+                ("mHashes", object(set_hashes)),
+                ("mArray", object(set_array)),
+                ("mSize", JavaValue::Int(3)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let decoded = collection(&graph, map).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::ArrayMap);
+        assert!(decoded.kind.is_map());
+        assert_eq!((decoded.size, decoded.capacity), (2, 4));
+        assert_eq!(
+            decoded.entries,
+            entries(&[(items[0], items[1]), (items[2], items[3])])
+        );
+        assert_eq!(decoded.backing_arrays, vec![hashes, array]);
+
+        let decoded = collection(&graph, set).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::ArraySet);
+        assert!(!decoded.kind.is_map());
+        assert_eq!((decoded.size, decoded.capacity), (3, 4));
+        assert_eq!(decoded.elements, items[..3].to_vec());
+    }
+
+    #[test]
+    fn sparse_arrays() {
+        let mut dump = DumpBuilder::new();
+        let items = objects(&mut dump, 3);
+        let deleted_class = dump.class("java.lang.Object", 0, &[]);
+        let deleted = dump.instance(deleted_class, &[]);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let fields = [
+            ("mKeys", JavaType::Object),
+            ("mValues", JavaType::Object),
+            ("mSize", JavaType::Int),
+        ];
+        let sparse_class = dump.class_with_statics(
+            "android.util.SparseArray",
+            0,
+            &fields,
+            &[("DELETED", object(deleted))],
+        );
+        let long_class = dump.class("android.util.LongSparseArray", 0, &fields);
+
+        let keys: Vec<JavaValue> = [1, 2, 3, 0].into_iter().map(JavaValue::Int).collect();
+        let keys = dump.primitive_array(JavaType::Int, &keys);
+        let values = dump.object_array(array_class, &[items[0], deleted, items[1], 0]);
+        let sparse = dump.instance(
+            sparse_class,
+            &[
+                ("mKeys", object(keys)),
+                ("mValues", object(values)),
+                ("mSize", JavaValue::Int(3)),
+            ],
+        );
+        let keys = dump.primitive_array(
+            JavaType::Long,
+            &[JavaValue::Long(1 << 40), JavaValue::Long(0)],
+        );
+        let values = dump.object_array(array_class, &[items[2], 0]);
+        let long = dump.instance(
+            long_class,
+            &[
+                ("mKeys", object(keys)),
+                ("mValues", object(values)),
+                ("mSize", JavaValue::Int(1)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        // the removed value is skipped
+        let decoded = collection(&graph, sparse).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::SparseArray);
+        assert_eq!((decoded.size, decoded.capacity), (2, 4));
+        assert_eq!(
+            decoded.entries,
+            vec![(JavaValue::Int(1), items[0]), (JavaValue::Int(3), items[1])]
+        );
+
+        let decoded = collection(&graph, long).unwrap();
+        assert_eq!(decoded.kind, CollectionKind::LongSparseArray);
+        assert_eq!(decoded.entries, vec![(JavaValue::Long(1 << 40), items[2])]);
+    }
+
+    #[test]
+    fn wasteful_collections() {
+        let mut dump = DumpBuilder::new();
+        let items = objects(&mut dump, 4);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let list_class = dump.class(
+            "java.util.ArrayList",
+            0,
+            &[("elementData", JavaType::Object), ("size", JavaType::Int)],
+        );
+        let empty_table = dump.object_array(array_class, &[0, 0]);
+        let map_class = dump.class_with_statics(
+            "java.util.HashMap",
+            0,
+            &[("table", JavaType::Object)],
+            &[("EMPTY_TABLE", object(empty_table))],
+        );
+        let model_class = dump.class(
+            "com.example.Model",
+            0,
+            &[("tags", JavaType::Object), ("cache", JavaType::Object)],
+        );
+        let list = |dump: &mut DumpBuilder, elements: &[u64], capacity: usize| {
+            let mut data = elements.to_vec();
+            data.resize(capacity, 0);
+            let data = dump.object_array(array_class, &data);
+            let list = dump.instance(
+                list_class,
+                &[
+                    ("elementData", object(data)),
+                    ("size", JavaValue::Int(elements.len() as i32)),
+                ],
+            );
+            (list, data)
+        };
+        let lists = [
+            list(&mut dump, &[], 10),
+            list(&mut dump, &[], 10),
+            list(&mut dump, &items[..1], 4),
+            list(&mut dump, &items[..2], 64),
+            list(&mut dump, &items[..3], 4),
+        ];
+        for (list, _) in &lists {
+            // every empty map shares EMPTY_TABLE, it wastes nothing
+            let map = dump.instance(map_class, &[("table", object(empty_table))]);
+            let model = dump.instance(
+                model_class,
+                &[("tags", object(*list)), ("cache", object(map))],
+            );
+            dump.root(model);
+        }
+        let (rooted, rooted_data) = list(&mut dump, &[], 10);
+        dump.root(rooted);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let size = |id: u64| graph.shallow_size(id);
+
+        let wasteful = find_wasteful_collections(&graph);
+        let summary: Vec<(&str, CollectionWaste, Vec<u64>, u64)> = wasteful
+            .iter()
+            .map(|group| {
+                assert_eq!(group.kind, CollectionKind::ArrayList);
+                (
+                    group.holder.as_str(),
+                    group.waste,
+                    group.collection_ids.clone(),
+                    group.wasted_bytes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "com.example.Model.tags",
+                    CollectionWaste::Oversized,
+                    vec![lists[3].0],
+                    size(lists[3].1) * 62 / 64,
+                ),
+                (
+                    "com.example.Model.tags",
+                    CollectionWaste::Empty,
+                    vec![lists[0].0, lists[1].0],
+                    size(lists[0].1) + size(lists[1].1),
+                ),
+                (
+                    "Unknown",
+                    CollectionWaste::Empty,
+                    vec![rooted],
+                    size(rooted_data)
+                ),
+                (
+                    "com.example.Model.tags",
+                    CollectionWaste::SingleElement,
+                    vec![lists[2].0],
+                    size(lists[2].1) * 3 / 4,
+                ),
+            ]
+        );
+        assert_eq!(
+            wasteful[1].to_string(),
+            format!(
+                "com.example.Model.tags: 2 empty ArrayLists = {} bytes",
+                size(lists[0].1) * 2
+            )
+        );
+    }
+
+    #[test]
+    fn formatted_sizes() {
+        assert_eq!(grouped(7), "7");
+        assert_eq!(grouped(1234567), "1,234,567");
+        assert_eq!(format_bytes(1023), "1023 bytes");
+        assert_eq!(format_bytes(1536), "2 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 / 2), "1.5 MB");
+    }
+}
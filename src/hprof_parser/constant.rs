@@ -48,4 +48,4 @@ pub const SHORT: u8 = 9;
 pub const INT: u8 = 10;
 pub const LONG: u8 = 11;
 
-pub const HPROF_HEADER_VERSION_SIZE: u8 = 19;
\ No newline at end of file
+pub const HPROF_HEADER_VERSION_SIZE: u8 = 19;
@@ -0,0 +1,480 @@
+use crate::hprof_parser::dominator::DominatorTree;
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::histogram::{ClassHistogram, ClassStats};
+use crate::hprof_parser::path::PathFinder;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// How one class changed between two dumps.
+#[derive(Debug, Clone)]
+pub struct ClassDelta {
+    pub class_name: String,
+    /// `None` if the class is new in the second dump
+    pub before: Option<ClassStats>,
+    /// `None` if the class is gone from the second dump
+    pub after: Option<ClassStats>,
+}
+
+impl ClassDelta {
+    pub fn is_new(&self) -> bool {
+        self.before.is_none()
+    }
+
+    pub fn is_gone(&self) -> bool {
+        self.after.is_none()
+    }
+
+    pub fn instance_count_delta(&self) -> i64 {
+        self.after.unwrap_or_default().instance_count as i64
+            - self.before.unwrap_or_default().instance_count as i64
+    }
+
+    pub fn shallow_size_delta(&self) -> i64 {
+        self.after.unwrap_or_default().shallow_size as i64
+            - self.before.unwrap_or_default().shallow_size as i64
+    }
+
+    /// `None` unless both histograms were built with retained sizes.
+    pub fn retained_size_delta(&self) -> Option<i64> {
+        let retained = |stats: Option<ClassStats>| match stats {
+            Some(stats) => stats.retained_size,
+            None => Some(0),
+        };
+        Some(retained(self.after)? as i64 - retained(self.before)? as i64)
+    }
+
+    fn is_unchanged(&self) -> bool {
+        self.instance_count_delta() == 0
+            && self.shallow_size_delta() == 0
+            && self.retained_size_delta().unwrap_or_default() == 0
+    }
+}
+
+/// Per class differences between two dumps, usually taken before and after a
+/// scenario. Classes are matched by name since object ids, including those of
+/// class objects, differ from one process to another.
+#[derive(Debug, Clone)]
+pub struct HeapDiff {
+    deltas: Vec<ClassDelta>,
+}
+
+impl HeapDiff {
+    pub fn new(before: &ClassHistogram, after: &ClassHistogram) -> Self {
+        let mut deltas: BTreeMap<&str, ClassDelta> = BTreeMap::new();
+        for row in before.rows() {
+            deltas.insert(
+                &row.class_name,
+                ClassDelta {
+                    class_name: row.class_name.clone(),
+                    before: Some(row.total),
+                    after: None,
+                },
+            );
+        }
+        for row in after.rows() {
+            deltas
+                .entry(&row.class_name)
+                .or_insert_with(|| ClassDelta {
+                    class_name: row.class_name.clone(),
+                    before: None,
+                    after: None,
+                })
+                .after = Some(row.total);
+        }
+
+        let mut deltas: Vec<ClassDelta> = deltas
+            .into_values()
+            .filter(|delta| !delta.is_unchanged())
+            .collect();
+        deltas.sort_by_key(|delta| std::cmp::Reverse(delta.shallow_size_delta().abs()));
+        Self { deltas }
+    }
+
+    /// Builds both histograms, with retained sizes.
+    pub fn compare(before: &HeapGraph, after: &HeapGraph) -> Self {
+        let before = ClassHistogram::new(before, Some(&DominatorTree::new(before)));
+        let after = ClassHistogram::new(after, Some(&DominatorTree::new(after)));
+        Self::new(&before, &after)
+    }
+
+    /// Classes that changed, largest shallow size change first.
+    pub fn deltas(&self) -> &[ClassDelta] {
+        &self.deltas
+    }
+
+    pub fn new_classes(&self) -> impl Iterator<Item = &ClassDelta> {
+        self.deltas.iter().filter(|delta| delta.is_new())
+    }
+
+    pub fn gone_classes(&self) -> impl Iterator<Item = &ClassDelta> {
+        self.deltas.iter().filter(|delta| delta.is_gone())
+    }
+}
+
+impl Display for HeapDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>12} {:>12}  class",
+            "count", "shallow", "retained"
+        )?;
+        for delta in &self.deltas {
+            let retained = delta
+                .retained_size_delta()
+                .map(|size| format!("{:+}", size))
+                .unwrap_or_else(|| "-".to_string());
+            let status = if delta.is_new() {
+                " (new)"
+            } else if delta.is_gone() {
+                " (gone)"
+            } else {
+                ""
+            };
+            writeln!(
+                f,
+                "{:>+10} {:>+12} {:>12}  {}{}",
+                delta.instance_count_delta(),
+                delta.shallow_size_delta(),
+                retained,
+                delta.class_name,
+                status
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An object found in every dump of a sequence.
+#[derive(Debug, Clone)]
+pub struct TrackedObject {
+    pub class_name: String,
+    /// id of the object in each dump
+    pub object_ids: Vec<u64>,
+    /// retained size of the object in each dump
+    pub retained_sizes: Vec<u64>,
+    /// the id changed at least once and the object was matched by its reference path
+    pub matched_by_path: bool,
+}
+
+impl TrackedObject {
+    /// Retained size in the last dump minus the one in the first.
+    pub fn growth(&self) -> i64 {
+        self.retained_sizes[self.retained_sizes.len() - 1] as i64 - self.retained_sizes[0] as i64
+    }
+
+    /// Never shrinks from one dump to the next and is larger at the end.
+    pub fn keeps_growing(&self) -> bool {
+        self.retained_sizes.windows(2).all(|w| w[0] <= w[1]) && self.growth() > 0
+    }
+}
+
+/// Follows objects through several dumps of the same process, taken one after
+/// the other. An object is matched by id, as ids stay the same within a
+/// process unless a moving collection happened in between. When the id does
+/// not match, the object is matched by class and by the signature of its
+/// shortest path from a gc root, provided that signature is unique.
+#[derive(Debug, Clone)]
+pub struct ObjectDiff {
+    survivors: Vec<TrackedObject>,
+}
+
+impl ObjectDiff {
+    /// `dumps` in the order they were taken, each with its dominator tree.
+    pub fn new(dumps: &[(&HeapGraph, &DominatorTree)]) -> Self {
+        let Some((first, first_dominators)) = dumps.first() else {
+            return Self {
+                survivors: Vec::new(),
+            };
+        };
+
+        let mut tracked: Vec<TrackedObject> = first_dominators
+            .reachable_ids()
+            .iter()
+            .filter_map(|id| {
+                Some(TrackedObject {
+                    class_name: first.type_name(*id)?,
+                    object_ids: vec![*id],
+                    retained_sizes: vec![first_dominators.retained_size(*id)],
+                    matched_by_path: false,
+                })
+            })
+            .collect();
+
+        for (step, window) in dumps.windows(2).enumerate() {
+            let (previous, _) = window[0];
+            let (graph, dominators) = window[1];
+
+            let mut claimed = HashSet::new();
+            let mut unmatched = Vec::new();
+            for (index, object) in tracked.iter_mut().enumerate() {
+                let id = object.object_ids[object.object_ids.len() - 1];
+                if dominators.is_reachable(id)
+                    && graph.type_name(id).as_deref() == Some(object.class_name.as_str())
+                {
+                    object.object_ids.push(id);
+                    object.retained_sizes.push(dominators.retained_size(id));
+                    claimed.insert(id);
+                } else {
+                    unmatched.push(index);
+                }
+            }
+
+            if !unmatched.is_empty() {
+                let signatures = unique_signatures(
+                    previous,
+                    unmatched.iter().map(|i| {
+                        let ids = &tracked[*i].object_ids;
+                        ids[ids.len() - 1]
+                    }),
+                );
+                let class_names: HashSet<&str> = unmatched
+                    .iter()
+                    .map(|i| tracked[*i].class_name.as_str())
+                    .collect();
+                let candidates = dominators.reachable_ids().iter().copied().filter(|id| {
+                    !claimed.contains(id)
+                        && graph
+                            .type_name(*id)
+                            .is_some_and(|name| class_names.contains(name.as_str()))
+                });
+                let by_signature: HashMap<String, u64> = unique_signatures(graph, candidates)
+                    .into_iter()
+                    .map(|(id, signature)| (signature, id))
+                    .collect();
+
+                for index in unmatched {
+                    let object = &mut tracked[index];
+                    let id = object.object_ids[object.object_ids.len() - 1];
+                    let Some(new_id) = signatures
+                        .get(&id)
+                        .and_then(|signature| by_signature.get(signature))
+                    else {
+                        continue;
+                    };
+                    object.object_ids.push(*new_id);
+                    object
+                        .retained_sizes
+                        .push(dominators.retained_size(*new_id));
+                    object.matched_by_path = true;
+                }
+            }
+
+            // objects matched neither way are considered collected
+            tracked.retain(|object| object.object_ids.len() == step + 2);
+        }
+
+        Self { survivors: tracked }
+    }
+
+    /// Objects present in every dump.
+    pub fn survivors(&self) -> &[TrackedObject] {
+        &self.survivors
+    }
+
+    /// Survivors whose retained size keeps growing, the slow leak signature,
+    /// largest growth first.
+    pub fn growing(&self) -> Vec<&TrackedObject> {
+        let mut growing: Vec<&TrackedObject> = self
+            .survivors
+            .iter()
+            .filter(|object| object.keeps_growing())
+            .collect();
+        growing.sort_by_key(|object| std::cmp::Reverse(object.growth()));
+        growing
+    }
+}
+
+/// id -> path signature, for the ids whose signature no other id shares.
+fn unique_signatures(graph: &HeapGraph, ids: impl Iterator<Item = u64>) -> HashMap<u64, String> {
+    let ids: Vec<u64> = ids.collect();
+    let mut signatures: HashMap<u64, String> = PathFinder::new(graph)
+        .shortest_paths(&ids)
+        .into_iter()
+        .map(|(id, path)| (id, path.signature(graph)))
+        .collect();
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for signature in signatures.values() {
+        *counts.entry(signature).or_default() += 1;
+    }
+    let shared: HashSet<String> = counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(signature, _)| signature.to_string())
+        .collect();
+    signatures.retain(|_, signature| !shared.contains(signature));
+    signatures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::{JavaType, JavaValue};
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    /// Each class with its number of instances, all of them gc roots.
+    fn dump(classes: &[(&str, usize)]) -> Vec<u8> {
+        let mut dump = DumpBuilder::new();
+        for (name, count) in classes {
+            let class_id = dump.class(name, 0, &[("value", JavaType::Long)]);
+            for _ in 0..*count {
+                let id = dump.instance(class_id, &[("value", JavaValue::Long(0))]);
+                dump.root(id);
+            }
+        }
+        dump.build()
+    }
+
+    #[test]
+    fn class_diff() {
+        let before = dump(&[
+            ("com.example.Grows", 2),
+            ("com.example.Gone", 1),
+            ("com.example.Same", 1),
+        ]);
+        let after = dump(&[
+            ("com.example.Grows", 5),
+            ("com.example.New", 1),
+            ("com.example.Same", 1),
+        ]);
+        let before = HeapGraph::from_bytes(&before).unwrap();
+        let after = HeapGraph::from_bytes(&after).unwrap();
+
+        let diff = HeapDiff::compare(&before, &after);
+        let names: Vec<&str> = diff
+            .deltas()
+            .iter()
+            .map(|d| d.class_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["com.example.Grows", "com.example.Gone", "com.example.New"]
+        );
+
+        let grows = &diff.deltas()[0];
+        let size = before.shallow_size(before.instances_of("com.example.Grows")[0]) as i64;
+        assert_eq!(grows.instance_count_delta(), 3);
+        assert_eq!(grows.shallow_size_delta(), 3 * size);
+        assert_eq!(grows.retained_size_delta(), Some(3 * size));
+        assert!(!grows.is_new() && !grows.is_gone());
+
+        let gone: Vec<&str> = diff.gone_classes().map(|d| d.class_name.as_str()).collect();
+        assert_eq!(gone, vec!["com.example.Gone"]);
+        assert_eq!(diff.deltas()[1].instance_count_delta(), -1);
+        let new: Vec<&str> = diff.new_classes().map(|d| d.class_name.as_str()).collect();
+        assert_eq!(new, vec!["com.example.New"]);
+        assert_eq!(diff.deltas()[2].shallow_size_delta(), size);
+    }
+
+    #[test]
+    fn class_diff_without_retained_sizes() {
+        let before = dump(&[("com.example.Grows", 1)]);
+        let after = dump(&[("com.example.Grows", 2)]);
+        let before = HeapGraph::from_bytes(&before).unwrap();
+        let after = HeapGraph::from_bytes(&after).unwrap();
+
+        let diff = HeapDiff::new(
+            &ClassHistogram::new(&before, None),
+            &ClassHistogram::new(&after, None),
+        );
+        assert_eq!(diff.deltas().len(), 1);
+        assert_eq!(diff.deltas()[0].instance_count_delta(), 1);
+        assert_eq!(diff.deltas()[0].retained_size_delta(), None);
+    }
+
+    /// A rooted holder whose cache keeps a `byte[]` of `cache_size` and,
+    /// with `temp`, a temporary object. `shift` moves every id as a moving
+    /// collection would.
+    fn session(shift: usize, cache_size: usize, temp: bool) -> (Vec<u8>, u64) {
+        let mut dump = DumpBuilder::new();
+        for _ in 0..shift {
+            dump.new_id();
+        }
+        let holder_class = dump.class(
+            "com.example.Holder",
+            0,
+            &[("cache", JavaType::Object), ("temp", JavaType::Object)],
+        );
+        let cache_class = dump.class("com.example.Cache", 0, &[("data", JavaType::Object)]);
+        let temp_class = dump.class("com.example.Temp", 0, &[]);
+        let data = dump.primitive_array(JavaType::Byte, &vec![JavaValue::Byte(0); cache_size]);
+        let cache = dump.instance(cache_class, &[("data", JavaValue::Object(data))]);
+        let temp_id = dump.new_id();
+        let holder = dump.instance(
+            holder_class,
+            &[
+                ("cache", JavaValue::Object(cache)),
+                ("temp", JavaValue::Object(if temp { temp_id } else { 0 })),
+            ],
+        );
+        if temp {
+            dump.instance_with_id(temp_id, temp_class, &[]);
+        }
+        dump.root(holder);
+        (dump.build(), cache)
+    }
+
+    #[test]
+    fn object_diff() {
+        let (first, first_cache) = session(0, 16, true);
+        let (second, second_cache) = session(0, 64, false);
+        let (third, third_cache) = session(1, 256, false);
+        assert_eq!(first_cache, second_cache);
+        assert_ne!(second_cache, third_cache);
+        let graphs: Vec<HeapGraph> = [&first, &second, &third]
+            .iter()
+            .map(|bytes| HeapGraph::from_bytes(bytes).unwrap())
+            .collect();
+        let trees: Vec<DominatorTree> = graphs.iter().map(DominatorTree::new).collect();
+        let dumps: Vec<(&HeapGraph, &DominatorTree)> = graphs.iter().zip(&trees).collect();
+
+        let diff = ObjectDiff::new(&dumps);
+        let mut names: Vec<&str> = diff
+            .survivors()
+            .iter()
+            .map(|object| object.class_name.as_str())
+            .collect();
+        names.sort();
+        // the temporary object is gone from the second dump
+        assert_eq!(
+            names,
+            vec!["byte[]", "com.example.Cache", "com.example.Holder"]
+        );
+
+        let cache = diff
+            .survivors()
+            .iter()
+            .find(|object| object.class_name == "com.example.Cache")
+            .unwrap();
+        // same id in the first two dumps, then matched by its path
+        assert_eq!(
+            cache.object_ids,
+            vec![first_cache, second_cache, third_cache]
+        );
+        assert!(cache.matched_by_path);
+        let sizes: Vec<u64> = (0..3)
+            .map(|i| trees[i].retained_size(cache.object_ids[i]))
+            .collect();
+        assert_eq!(cache.retained_sizes, sizes);
+        assert!(sizes[0] < sizes[1] && sizes[1] < sizes[2]);
+        assert_eq!(cache.growth(), (sizes[2] - sizes[0]) as i64);
+        assert!(cache.keeps_growing());
+        assert_eq!(diff.growing().len(), 3);
+    }
+
+    #[test]
+    fn object_diff_of_one_dump() {
+        let (bytes, _) = session(0, 16, true);
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let tree = DominatorTree::new(&graph);
+
+        let diff = ObjectDiff::new(&[(&graph, &tree)]);
+        assert_eq!(diff.survivors().len(), 4);
+        assert!(diff
+            .survivors()
+            .iter()
+            .all(|object| !object.matched_by_path));
+        assert!(diff.growing().is_empty());
+        assert!(ObjectDiff::new(&[]).survivors().is_empty());
+    }
+}
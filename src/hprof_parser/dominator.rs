@@ -0,0 +1,337 @@
+use crate::hprof_parser::graph::{HeapGraph, ReferenceStrength};
+use crate::hprof_parser::snapshot::SubTag;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+const NONE: usize = usize::MAX;
+
+/// Dominator tree of the strongly reachable part of the heap, rooted at a
+/// virtual node that points at every gc root.
+///
+/// An object dominates another when every strong path from the roots to the
+/// second one goes through it, the retained size of an object is the size of
+/// everything it dominates, i.e. what would be freed if it were collected.
+/// Built with the Lengauer-Tarjan algorithm.
+pub struct DominatorTree {
+    /// dfs number -> object id, 0 is the virtual root
+    ids: Vec<u64>,
+    index: HashMap<u64, usize>,
+    idom: Vec<usize>,
+    retained: Vec<u64>,
+}
+
+impl DominatorTree {
+    pub fn new(graph: &HeapGraph) -> Self {
+        let mut roots: Vec<u64> = Vec::new();
+        for root in graph.gc_roots() {
+            // pending finalization is not a strong reference
+            if matches!(root, SubTag::RootFinalizing(_)) {
+                continue;
+            }
+            if let Some(id) = root.object_id() {
+                if graph.object(id).is_some() {
+                    roots.push(id);
+                }
+            }
+        }
+
+        // depth first numbering, remembering the tree parent and every predecessor
+        let mut ids = vec![0];
+        let mut index: HashMap<u64, usize> = HashMap::new();
+        let mut parent = vec![NONE];
+        let mut preds: Vec<Vec<usize>> = vec![Vec::new()];
+        let mut stack: Vec<(usize, Vec<u64>, usize)> = vec![(0, roots, 0)];
+
+        while let Some((v, successors, pos)) = stack.last_mut() {
+            let v = *v;
+            let Some(target) = successors.get(*pos).copied() else {
+                stack.pop();
+                continue;
+            };
+            *pos += 1;
+            let w = match index.get(&target) {
+                Some(w) => *w,
+                None => {
+                    let w = ids.len();
+                    ids.push(target);
+                    index.insert(target, w);
+                    parent.push(v);
+                    preds.push(Vec::new());
+                    let successors = graph
+                        .edges(target)
+                        .into_iter()
+                        .filter(|edge| edge.strength == ReferenceStrength::Strong)
+                        .map(|edge| edge.target)
+                        .collect();
+                    stack.push((w, successors, 0));
+                    w
+                }
+            };
+            preds[w].push(v);
+        }
+
+        let n = ids.len();
+        let mut semi: Vec<usize> = (0..n).collect();
+        let mut label: Vec<usize> = (0..n).collect();
+        let mut ancestor = vec![NONE; n];
+        let mut idom = vec![0; n];
+        let mut bucket: Vec<Vec<usize>> = vec![Vec::new(); n];
+
+        for w in (1..n).rev() {
+            for &v in &preds[w] {
+                let u = eval(v, &mut ancestor, &mut label, &semi);
+                if semi[u] < semi[w] {
+                    semi[w] = semi[u];
+                }
+            }
+            bucket[semi[w]].push(w);
+            let p = parent[w];
+            ancestor[w] = p;
+            for v in std::mem::take(&mut bucket[p]) {
+                let u = eval(v, &mut ancestor, &mut label, &semi);
+                idom[v] = if semi[u] < semi[v] { u } else { p };
+            }
+        }
+        for w in 1..n {
+            if idom[w] != semi[w] {
+                idom[w] = idom[idom[w]];
+            }
+        }
+
+        // a dominator always has a smaller dfs number than the objects it dominates
+        let mut retained: Vec<u64> = ids.iter().map(|id| graph.shallow_size(*id)).collect();
+        retained[0] = 0;
+        for w in (1..n).rev() {
+            retained[idom[w]] += retained[w];
+        }
+
+        Self {
+            ids,
+            index,
+            idom,
+            retained,
+        }
+    }
+
+    pub fn is_reachable(&self, id: u64) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    /// `None` if the object is not strongly reachable or only dominated by the virtual root.
+    pub fn immediate_dominator(&self, id: u64) -> Option<u64> {
+        let w = *self.index.get(&id)?;
+        match self.idom[w] {
+            0 => None,
+            d => Some(self.ids[d]),
+        }
+    }
+
+    /// Bytes freed if the object was collected, 0 if it is not strongly reachable.
+    pub fn retained_size(&self, id: u64) -> u64 {
+        self.index
+            .get(&id)
+            .map(|w| self.retained[*w])
+            .unwrap_or_default()
+    }
+
+    /// Objects dominated, directly or not, by another object with the same
+    /// `key`. One walk down the tree, counting the keys of the objects on the
+    /// path from the root.
+    pub fn dominated_by_same_key<K, F>(&self, key: F) -> HashSet<u64>
+    where
+        K: Hash + Eq,
+        F: Fn(u64) -> K,
+    {
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); self.ids.len()];
+        for w in 1..self.ids.len() {
+            children[self.idom[w]].push(w);
+        }
+
+        let mut nested = HashSet::new();
+        let mut on_path: HashMap<K, usize> = HashMap::new();
+        // the key is set once the object is on the path, popping it again leaves it
+        let mut stack: Vec<(usize, Option<K>)> = children[0].iter().map(|w| (*w, None)).collect();
+        while let Some((w, entered)) = stack.pop() {
+            match entered {
+                None => {
+                    let k = key(self.ids[w]);
+                    let count = on_path.entry(k).or_default();
+                    if *count > 0 {
+                        nested.insert(self.ids[w]);
+                    }
+                    *count += 1;
+                    stack.push((w, Some(key(self.ids[w]))));
+                    stack.extend(children[w].iter().map(|c| (*c, None)));
+                }
+                Some(k) => {
+                    if let Some(count) = on_path.get_mut(&k) {
+                        *count -= 1;
+                    }
+                }
+            }
+        }
+        nested
+    }
+
+    /// Sum of `value` over the objects each object dominates, itself included,
+    /// like the retained size with `value` in place of the shallow size. One
+    /// walk up the tree, objects whose sum is 0 are left out.
+    pub fn retained_sum<F>(&self, value: F) -> HashMap<u64, u64>
+    where
+        F: Fn(u64) -> u64,
+    {
+        let mut sums: Vec<u64> = self.ids.iter().map(|id| value(*id)).collect();
+        sums[0] = 0;
+        for w in (1..self.ids.len()).rev() {
+            sums[self.idom[w]] += sums[w];
+        }
+        self.ids
+            .iter()
+            .zip(sums)
+            .skip(1)
+            .filter(|(_, sum)| *sum > 0)
+            .map(|(id, sum)| (*id, sum))
+            .collect()
+    }
+
+    /// Strongly reachable objects.
+    pub fn reachable_ids(&self) -> &[u64] {
+        &self.ids[1..]
+    }
+}
+
+fn eval(v: usize, ancestor: &mut [usize], label: &mut [usize], semi: &[usize]) -> usize {
+    if ancestor[v] == NONE {
+        return v;
+    }
+    compress(v, ancestor, label, semi);
+    label[v]
+}
+
+fn compress(v: usize, ancestor: &mut [usize], label: &mut [usize], semi: &[usize]) {
+    let mut path = Vec::new();
+    let mut u = v;
+    while ancestor[ancestor[u]] != NONE {
+        path.push(u);
+        u = ancestor[u];
+    }
+    while let Some(u) = path.pop() {
+        let a = ancestor[u];
+        if semi[label[a]] < semi[label[u]] {
+            label[u] = label[a];
+        }
+        ancestor[u] = ancestor[a];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::{JavaType, JavaValue};
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    /// `count` objects with up to 3 references given as `(from, to)` indexes,
+    /// the first `roots` of them being gc roots.
+    fn build(count: usize, roots: usize, edges: &[(usize, usize)]) -> (Vec<u8>, Vec<u64>) {
+        let mut dump = DumpBuilder::new();
+        let fields = [
+            ("a", JavaType::Object),
+            ("b", JavaType::Object),
+            ("c", JavaType::Object),
+        ];
+        let node = dump.class("Node", 0, &fields);
+        let ids: Vec<u64> = (0..count).map(|_| dump.new_id()).collect();
+        for (from, id) in ids.iter().enumerate() {
+            let values: Vec<(&str, JavaValue)> = edges
+                .iter()
+                .filter(|(source, _)| *source == from)
+                .zip(["a", "b", "c"])
+                .map(|((_, to), field)| (field, JavaValue::Object(ids[*to])))
+                .collect();
+            dump.instance_with_id(*id, node, &values);
+        }
+        for id in &ids[..roots] {
+            dump.root(*id);
+        }
+        (dump.build(), ids)
+    }
+
+    fn idoms(tree: &DominatorTree, ids: &[u64]) -> Vec<Option<usize>> {
+        ids.iter()
+            .map(|id| {
+                tree.immediate_dominator(*id)
+                    .map(|dominator| ids.iter().position(|id| *id == dominator).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chain_and_diamond() {
+        // 0 -> 1 -> 2, 1 -> 3, 2 -> 4, 3 -> 4
+        let (bytes, ids) = build(5, 1, &[(0, 1), (1, 2), (1, 3), (2, 4), (3, 4)]);
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let tree = DominatorTree::new(&graph);
+        assert_eq!(
+            idoms(&tree, &ids),
+            vec![None, Some(0), Some(1), Some(1), Some(1)]
+        );
+        let total: u64 = ids.iter().map(|id| graph.shallow_size(*id)).sum();
+        assert_eq!(tree.retained_size(ids[0]), total);
+        assert_eq!(tree.retained_size(ids[2]), graph.shallow_size(ids[2]));
+    }
+
+    #[test]
+    fn semidominator_is_not_the_dominator() {
+        // 0 -> 1 -> 2 -> 3 and 1 -> 3 make 1 the semidominator of 3, but
+        // 0 -> 4 -> 2 goes around it; 3 -> 2 adds a cycle
+        let (bytes, ids) = build(
+            5,
+            1,
+            &[(0, 1), (1, 2), (1, 3), (2, 3), (0, 4), (4, 2), (3, 2)],
+        );
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let tree = DominatorTree::new(&graph);
+        assert_eq!(
+            idoms(&tree, &ids),
+            vec![None, Some(0), Some(0), Some(0), Some(0)]
+        );
+    }
+
+    #[test]
+    fn objects_of_several_roots_are_only_dominated_by_the_virtual_root() {
+        // roots 0 and 1 both reach 2, 3 is unreachable
+        let (bytes, ids) = build(4, 2, &[(0, 2), (1, 2), (3, 0)]);
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let tree = DominatorTree::new(&graph);
+        assert_eq!(idoms(&tree, &ids), vec![None, None, None, None]);
+        assert!(tree.is_reachable(ids[2]));
+        assert!(!tree.is_reachable(ids[3]));
+        assert_eq!(tree.retained_size(ids[0]), graph.shallow_size(ids[0]));
+        assert_eq!(tree.retained_size(ids[3]), 0);
+    }
+
+    #[test]
+    fn same_key_below_in_the_tree() {
+        // 0 -> 1 -> 2 -> 3, keyed by parity
+        let (bytes, ids) = build(4, 1, &[(0, 1), (1, 2), (2, 3)]);
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let tree = DominatorTree::new(&graph);
+        let nested =
+            tree.dominated_by_same_key(|id| ids.iter().position(|i| *i == id).unwrap() % 2);
+        assert_eq!(nested, HashSet::from([ids[2], ids[3]]));
+    }
+
+    #[test]
+    fn retained_sums() {
+        // 0 -> 1 -> 2, 0 -> 3, 4 is unreachable
+        let (bytes, ids) = build(5, 1, &[(0, 1), (1, 2), (0, 3)]);
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let tree = DominatorTree::new(&graph);
+        let values = HashMap::from([(ids[2], 5), (ids[3], 7), (ids[4], 11)]);
+        let sums = tree.retained_sum(|id| values.get(&id).copied().unwrap_or_default());
+        assert_eq!(
+            sums,
+            HashMap::from([(ids[0], 12), (ids[1], 5), (ids[2], 5), (ids[3], 7)])
+        );
+    }
+}
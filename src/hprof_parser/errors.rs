@@ -27,4 +27,13 @@ pub enum Error {
 
     #[error("unknown subtag: {0}")]
     UnknownSubTag(u8),
+
+    #[error("invalid mapping at line {line}: {content}")]
+    InvalidMapping { line: usize, content: String },
+
+    #[error(transparent)]
+    PngEncoding(#[from] png::EncodingError),
+
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
 }
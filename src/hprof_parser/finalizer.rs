@@ -0,0 +1,295 @@
+use crate::hprof_parser::graph::{HeapGraph, ReferenceStrength};
+use crate::hprof_parser::reachability::{Reachability, ReachabilityMap};
+use crate::hprof_parser::snapshot::{JavaValue, SubTag};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// Pending objects of one class.
+#[derive(Debug, Clone)]
+pub struct PendingFinalization {
+    pub class_name: String,
+    pub object_ids: Vec<u64>,
+    /// the objects themselves and what only objects of this class keep alive
+    pub retained_size: u64,
+}
+
+/// Objects waiting for their `finalize()` to run, which keep everything they
+/// reference alive until the finalizer thread gets to them.
+#[derive(Debug, Clone)]
+pub struct FinalizerQueue {
+    /// largest retained size first
+    pub classes: Vec<PendingFinalization>,
+    /// size of everything that would be freed once the queue is drained
+    pub retained_size: u64,
+    /// part of `retained_size` kept alive by pending objects of several
+    /// classes, credited to none of them
+    pub shared_size: u64,
+}
+
+impl FinalizerQueue {
+    /// An object is pending when it is a ROOT_FINALIZING root, the `zombie`
+    /// of an enqueued `java.lang.ref.FinalizerReference`, or the `referent` of
+    /// a FinalizerReference (or hotspot `java.lang.ref.Finalizer`) and only
+    /// reachable through it.
+    pub fn new(graph: &HeapGraph) -> Self {
+        let mut pending: BTreeSet<u64> = graph
+            .gc_roots()
+            .filter_map(|root| match root {
+                SubTag::RootFinalizing(id) => Some(*id),
+                _ => None,
+            })
+            .collect();
+
+        let reachability = ReachabilityMap::new(graph);
+        for class_name in [
+            "java.lang.ref.FinalizerReference",
+            "java.lang.ref.Finalizer",
+        ] {
+            for reference in graph.instances_of(class_name) {
+                if let Some(JavaValue::Object(zombie)) = graph.field(reference, "zombie") {
+                    pending.insert(zombie);
+                }
+                if let Some(referent) = graph.referent(reference) {
+                    if reachability.reachability(referent) == Reachability::Finalizer {
+                        pending.insert(referent);
+                    }
+                }
+            }
+        }
+        pending.retain(|id| *id != 0 && graph.object(*id).is_some());
+
+        // what stays alive without going through a pending object, soft and
+        // weak references included as draining the queue does not clear them
+        let mut alive: HashSet<u64> = HashSet::new();
+        let mut stack: Vec<u64> = graph
+            .gc_roots()
+            .filter(|root| !matches!(root, SubTag::RootFinalizing(_)))
+            .filter_map(|root| root.object_id())
+            .collect();
+        while let Some(id) = stack.pop() {
+            if pending.contains(&id) || !alive.insert(id) {
+                continue;
+            }
+            stack.extend(
+                graph
+                    .edges(id)
+                    .into_iter()
+                    .filter(|edge| Reachability::from(edge.strength) < Reachability::Finalizer)
+                    .map(|edge| edge.target),
+            );
+        }
+
+        // the rest of what pending objects reach is held hostage: an object
+        // only reached from pending objects of one class is credited to that
+        // class, one reached from several classes is only part of the total
+        let mut class_names: Vec<String> = Vec::new();
+        let mut class_indexes: HashMap<String, usize> = HashMap::new();
+        let pending_classes: Vec<usize> = pending
+            .iter()
+            .map(|id| {
+                let name = graph.type_name(*id).unwrap_or_default();
+                *class_indexes.entry(name.clone()).or_insert_with(|| {
+                    class_names.push(name);
+                    class_names.len() - 1
+                })
+            })
+            .collect();
+        // object -> index of the only class reaching it, `None` once shared
+        let mut holders: HashMap<u64, Option<usize>> = HashMap::new();
+        let mut stack: Vec<(u64, Option<usize>)> = Vec::new();
+        for (id, class) in pending.iter().zip(&pending_classes) {
+            let class = Some(*class);
+            holders.insert(*id, class);
+            stack.extend(strong_targets(graph, *id).into_iter().map(|t| (t, class)));
+        }
+        // an object changes holder at most twice, so this stays linear
+        while let Some((id, class)) = stack.pop() {
+            if alive.contains(&id) || pending.contains(&id) {
+                continue;
+            }
+            let holder = match holders.get(&id) {
+                None => class,
+                Some(Some(holder)) if Some(*holder) != class => None,
+                Some(_) => continue,
+            };
+            holders.insert(id, holder);
+            stack.extend(strong_targets(graph, id).into_iter().map(|t| (t, holder)));
+        }
+
+        let mut class_sizes: Vec<u64> = vec![0; class_names.len()];
+        let mut retained_size = 0;
+        let mut shared_size = 0;
+        for (id, holder) in &holders {
+            let size = graph.shallow_size(*id);
+            retained_size += size;
+            match holder {
+                Some(class) => class_sizes[*class] += size,
+                None => shared_size += size,
+            }
+        }
+
+        let mut classes: Vec<PendingFinalization> = class_names
+            .into_iter()
+            .zip(class_sizes)
+            .map(|(class_name, retained_size)| PendingFinalization {
+                class_name,
+                object_ids: Vec::new(),
+                retained_size,
+            })
+            .collect();
+        for (id, class) in pending.iter().zip(pending_classes) {
+            classes[class].object_ids.push(*id);
+        }
+        classes.sort_by(|a, b| {
+            b.retained_size
+                .cmp(&a.retained_size)
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        Self {
+            classes,
+            retained_size,
+            shared_size,
+        }
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.classes
+            .iter()
+            .map(|class| class.object_ids.len())
+            .sum()
+    }
+}
+
+fn strong_targets(graph: &HeapGraph, id: u64) -> Vec<u64> {
+    graph
+        .edges(id)
+        .into_iter()
+        .filter(|edge| edge.strength == ReferenceStrength::Strong)
+        .map(|edge| edge.target)
+        .collect()
+}
+
+impl Display for FinalizerQueue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} objects pending finalization, retaining {} bytes ({} shared between classes)",
+            self.pending_count(),
+            self.retained_size,
+            self.shared_size
+        )?;
+        for class in &self.classes {
+            writeln!(
+                f,
+                "{:>10} {:>12}  {}",
+                class.object_ids.len(),
+                class.retained_size,
+                class.class_name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::JavaType;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    #[test]
+    fn hostages() {
+        let mut dump = DumpBuilder::new();
+        let reference = dump.class(
+            "java.lang.ref.Reference",
+            0,
+            &[("referent", JavaType::Object)],
+        );
+        let finalizer = dump.class(
+            "java.lang.ref.FinalizerReference",
+            reference,
+            &[("zombie", JavaType::Object)],
+        );
+        let fields = [("own", JavaType::Object), ("shared", JavaType::Object)];
+        let stream_class = dump.class("com.example.Stream", 0, &fields);
+        let socket_class = dump.class("com.example.Socket", 0, &fields);
+        let holder_class = dump.class("com.example.Holder", 0, &[("refs", JavaType::Object)]);
+        let buffer = |dump: &mut DumpBuilder, size| {
+            dump.primitive_array(JavaType::Byte, &vec![JavaValue::Byte(0); size])
+        };
+
+        let live = buffer(&mut dump, 30);
+        let shared = buffer(&mut dump, 20);
+        let own = [
+            buffer(&mut dump, 100),
+            buffer(&mut dump, 50),
+            buffer(&mut dump, 10),
+        ];
+        let stream = |dump: &mut DumpBuilder, class_id, own: u64, shared: u64| {
+            dump.instance(
+                class_id,
+                &[
+                    ("own", JavaValue::Object(own)),
+                    ("shared", JavaValue::Object(shared)),
+                ],
+            )
+        };
+        // what a live object also references is not held hostage
+        let finalizing = stream(&mut dump, stream_class, own[0], live);
+        let referent = stream(&mut dump, stream_class, own[1], shared);
+        let zombie = stream(&mut dump, socket_class, own[2], shared);
+        let reachable = stream(&mut dump, stream_class, live, 0);
+        let refs: Vec<u64> = [
+            ("referent", referent),
+            ("zombie", zombie),
+            ("referent", reachable),
+        ]
+        .into_iter()
+        .map(|(field, id)| dump.instance(finalizer, &[(field, JavaValue::Object(id))]))
+        .collect();
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let array = dump.object_array(array_class, &refs);
+        let holder = dump.instance(holder_class, &[("refs", JavaValue::Object(array))]);
+        dump.root(holder);
+        dump.root(reachable);
+        dump.finalizing_root(finalizing);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let size = |id: u64| graph.shallow_size(id);
+
+        let queue = FinalizerQueue::new(&graph);
+        assert_eq!(queue.pending_count(), 3);
+        let classes: Vec<(&str, Vec<u64>, u64)> = queue
+            .classes
+            .iter()
+            .map(|class| {
+                (
+                    class.class_name.as_str(),
+                    class.object_ids.clone(),
+                    class.retained_size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            classes,
+            vec![
+                (
+                    "com.example.Stream",
+                    vec![finalizing, referent],
+                    size(finalizing) + size(own[0]) + size(referent) + size(own[1]),
+                ),
+                (
+                    "com.example.Socket",
+                    vec![zombie],
+                    size(zombie) + size(own[2])
+                ),
+            ]
+        );
+        assert_eq!(queue.shared_size, size(shared));
+        let credited: u64 = queue.classes.iter().map(|class| class.retained_size).sum();
+        assert_eq!(queue.retained_size, credited + size(shared));
+        assert!(queue
+            .to_string()
+            .starts_with("3 objects pending finalization"));
+    }
+}
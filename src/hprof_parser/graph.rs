@@ -0,0 +1,748 @@
+use crate::hprof_parser::mapping::ProguardMapping;
+use crate::hprof_parser::size_model::SizeModel;
+use crate::hprof_parser::snapshot::{JavaType, JavaValue, Record, Slice, Snapshot, SubTag};
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+
+/// `java.lang.Object` exposes its header to the dump as two instance fields,
+/// they are already covered by [`SizeModel::object_header`].
+const HEADER_FIELDS: [&str; 2] = ["shadow$_klass_", "shadow$_monitor_"];
+
+/// Format version written by ART, hotspot writes `JAVA PROFILE 1.0.2`.
+const ART_VERSION: &str = "JAVA PROFILE 1.0.3";
+
+/// Heap of objects dumped before any [`SubTag::HeapDumpInfo`], e.g. by hotspot.
+const DEFAULT_HEAP_ID: u32 = 0;
+const DEFAULT_HEAP_NAME: &str = "default";
+
+/// Declares the `referent` field of every reference class.
+const REFERENCE_CLASS: &str = "java.lang.ref.Reference";
+
+/// `java.lang.ref.Reference` subclasses whose `referent` does not keep the object alive.
+const REFERENCE_CLASSES: [(&str, ReferenceStrength); 5] = [
+    ("java.lang.ref.SoftReference", ReferenceStrength::Soft),
+    ("java.lang.ref.WeakReference", ReferenceStrength::Weak),
+    ("java.lang.ref.PhantomReference", ReferenceStrength::Phantom),
+    // android
+    (
+        "java.lang.ref.FinalizerReference",
+        ReferenceStrength::Finalizer,
+    ),
+    // hotspot
+    ("java.lang.ref.Finalizer", ReferenceStrength::Finalizer),
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ReferenceStrength {
+    Strong,
+    Soft,
+    Weak,
+    Phantom,
+    Finalizer,
+}
+
+/// A decoded instance or static field.
+#[derive(Debug, Copy, Clone)]
+pub struct FieldValue<'a> {
+    /// class that declares the field
+    pub class_id: u64,
+    pub name: &'a str,
+    pub value: JavaValue,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EdgeKind<'a> {
+    InstanceField { class_id: u64, name: &'a str },
+    StaticField { class_id: u64, name: &'a str },
+    ArrayElement(usize),
+}
+
+/// A STACK_FRAME record with its strings resolved.
+#[derive(Debug, Copy, Clone)]
+pub struct StackFrame<'a> {
+    pub method_name: &'a str,
+    pub method_signature: &'a str,
+    pub source_file: &'a str,
+    /// class declaring the method, `None` if it was not loaded in the dump
+    pub class_id: Option<u64>,
+    /// positive line number, or -1 unknown, -2 compiled method, -3 native method
+    pub line_number: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct StackTrace {
+    pub serial_number: u32,
+    pub thread_serial_number: u32,
+    /// innermost frame first
+    pub frame_ids: Vec<u64>,
+}
+
+/// An outgoing reference of an object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Edge<'a> {
+    pub kind: EdgeKind<'a>,
+    pub strength: ReferenceStrength,
+    pub target: u64,
+}
+
+/// Index over the records of a parsed dump: strings, class names, objects by id and gc roots.
+pub struct HeapGraph<'a> {
+    id_size: usize,
+    size_model: SizeModel,
+    strings: HashMap<u64, &'a str>,
+    /// deobfuscated field names by declaring class and name string
+    field_names: HashMap<(u64, u64), &'a str>,
+    class_names: HashMap<u64, String>,
+    class_ids_by_name: HashMap<String, Vec<u64>>,
+    objects: HashMap<u64, SubTag<'a>>,
+    /// heap the object was dumped in, see [`SubTag::HeapDumpInfo`]
+    object_heaps: HashMap<u64, u32>,
+    heap_names: HashMap<u32, &'a str>,
+    roots: Vec<SubTag<'a>>,
+    stack_frames: HashMap<u64, StackFrame<'a>>,
+    stack_traces: HashMap<u32, StackTrace>,
+    /// `ClassDump.instance_size` is the object size of ART, header and
+    /// padding included, while hotspot only dumps the bytes of the fields
+    art_instance_sizes: bool,
+    instance_sizes: HashMap<u64, u64>,
+    reference_classes: HashMap<u64, ReferenceStrength>,
+}
+
+impl<'a> HeapGraph<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        let snapshot = Snapshot::new(bytes)?;
+        snapshot.parse_records()?;
+        Ok(Self::from_snapshot(snapshot))
+    }
+
+    pub fn from_snapshot(snapshot: Snapshot<'a>) -> Self {
+        let id_size = snapshot.header().id_size as usize;
+        let art_instance_sizes = snapshot.header().version == ART_VERSION;
+        let records = snapshot.into_records();
+
+        let mut strings = HashMap::new();
+        let mut class_name_ids = Vec::new();
+        let mut objects = HashMap::new();
+        let mut object_heaps = HashMap::new();
+        let mut heap_name_ids = HashMap::new();
+        let mut heap_id = DEFAULT_HEAP_ID;
+        let mut roots = Vec::new();
+        let mut class_serials = HashMap::new();
+        let mut frame_records = Vec::new();
+        let mut stack_traces = HashMap::new();
+
+        for record in records {
+            match record {
+                Record::String { id, content } => {
+                    strings.insert(id, content);
+                }
+                Record::LoadClass {
+                    serial_number,
+                    object_id,
+                    class_name_id,
+                    ..
+                } => {
+                    class_name_ids.push((object_id, class_name_id));
+                    class_serials.insert(serial_number, object_id);
+                }
+                Record::StackFrame {
+                    id,
+                    method_name_id,
+                    method_signature_id,
+                    source_file_name_id,
+                    class_serial_number,
+                    line_no,
+                } => frame_records.push((
+                    id,
+                    [method_name_id, method_signature_id, source_file_name_id],
+                    class_serial_number,
+                    line_no,
+                )),
+                Record::StackTrace {
+                    serial_number,
+                    thread_serial_number,
+                    stack_frame_ids,
+                } => {
+                    stack_traces.insert(
+                        serial_number,
+                        StackTrace {
+                            serial_number,
+                            thread_serial_number,
+                            frame_ids: stack_frame_ids,
+                        },
+                    );
+                }
+                Record::HeapDump(subtags) => {
+                    for subtag in subtags {
+                        if let SubTag::HeapDumpInfo {
+                            heap_id: id,
+                            heap_name_id,
+                        } = subtag
+                        {
+                            heap_id = id;
+                            heap_name_ids.insert(id, heap_name_id);
+                        }
+                        if subtag.is_object() {
+                            if let Some(id) = subtag.object_id() {
+                                objects.insert(id, subtag);
+                                if heap_id != DEFAULT_HEAP_ID {
+                                    object_heaps.insert(id, heap_id);
+                                }
+                            }
+                        } else {
+                            roots.push(subtag);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut class_names = HashMap::new();
+        let mut class_ids_by_name: HashMap<String, Vec<u64>> = HashMap::new();
+        for (class_id, name_id) in class_name_ids {
+            if let Some(name) = strings.get(&name_id) {
+                // hotspot uses the internal form `java/lang/String`
+                let name = name.replace('/', ".");
+                class_ids_by_name
+                    .entry(name.clone())
+                    .or_default()
+                    .push(class_id);
+                class_names.insert(class_id, name);
+            }
+        }
+
+        let string = |id| strings.get(&id).copied().unwrap_or_default();
+        let stack_frames = frame_records
+            .into_iter()
+            .map(
+                |(id, [method, signature, source], class_serial, line_number)| {
+                    let frame = StackFrame {
+                        method_name: string(method),
+                        method_signature: string(signature),
+                        source_file: string(source),
+                        class_id: class_serials.get(&class_serial).copied(),
+                        line_number,
+                    };
+                    (id, frame)
+                },
+            )
+            .collect();
+
+        let heap_names = heap_name_ids
+            .into_iter()
+            .filter_map(|(id, name_id)| Some((id, *strings.get(&name_id)?)))
+            .collect();
+
+        let mut graph = Self {
+            id_size,
+            size_model: SizeModel::default(),
+            strings,
+            field_names: HashMap::new(),
+            class_names,
+            class_ids_by_name,
+            objects,
+            object_heaps,
+            heap_names,
+            roots,
+            stack_frames,
+            stack_traces,
+            art_instance_sizes,
+            instance_sizes: HashMap::new(),
+            reference_classes: HashMap::new(),
+        };
+        graph.compute_instance_sizes();
+        graph.compute_reference_classes();
+        graph
+    }
+
+    /// See [`Self::set_size_model`].
+    pub fn with_size_model(mut self, size_model: SizeModel) -> Self {
+        self.set_size_model(size_model);
+        self
+    }
+
+    /// ART dumps the real size of instances, which is always preferred: on
+    /// ART dumps the model only affects arrays and classes dumped with an
+    /// instance size of 0. Every object of other dumps is affected.
+    pub fn set_size_model(&mut self, size_model: SizeModel) {
+        self.size_model = size_model;
+        self.compute_instance_sizes();
+    }
+
+    /// Renames classes, fields and stack frame methods back to the names of
+    /// the source. Fields are renamed with the mapping of the class declaring
+    /// them, so inherited fields get the name their super class gave them.
+    pub fn with_mapping(mut self, mapping: &'a ProguardMapping) -> Self {
+        // members are mapped by the obfuscated name of their class
+        for frame in self.stack_frames.values_mut() {
+            let Some(class_name) = frame.class_id.and_then(|id| self.class_names.get(&id)) else {
+                continue;
+            };
+            if let Some(method) = mapping.method(class_name, frame.method_name, frame.line_number) {
+                frame.method_name = method.name;
+                frame.line_number = method.line_number;
+            }
+            if let Some(source_file) = mapping.source_file(class_name) {
+                frame.source_file = source_file;
+            }
+        }
+        for subtag in self.objects.values() {
+            let SubTag::ClassDump {
+                class_object_id,
+                static_fields,
+                instant_fields,
+                ..
+            } = subtag
+            else {
+                continue;
+            };
+            let Some(class_name) = self.class_names.get(class_object_id) else {
+                continue;
+            };
+            let name_ids = static_fields
+                .iter()
+                .map(|field| field.name_string_id)
+                .chain(instant_fields.iter().map(|field| field.name_string_id));
+            for name_id in name_ids {
+                let original = self
+                    .strings
+                    .get(&name_id)
+                    .and_then(|name| mapping.field_name(class_name, name));
+                if let Some(original) = original {
+                    self.field_names
+                        .insert((*class_object_id, name_id), original);
+                }
+            }
+        }
+
+        for name in self.class_names.values_mut() {
+            if let Some(original) = mapping.type_name(name) {
+                *name = original;
+            }
+        }
+        self.class_ids_by_name.clear();
+        for (class_id, name) in &self.class_names {
+            self.class_ids_by_name
+                .entry(name.clone())
+                .or_default()
+                .push(*class_id);
+        }
+        self
+    }
+
+    pub fn size_model(&self) -> &SizeModel {
+        &self.size_model
+    }
+
+    pub fn id_size(&self) -> usize {
+        self.id_size
+    }
+
+    pub fn string(&self, id: u64) -> Option<&'a str> {
+        self.strings.get(&id).copied()
+    }
+
+    pub fn class_name(&self, class_id: u64) -> Option<&str> {
+        self.class_names.get(&class_id).map(String::as_str)
+    }
+
+    /// All loaded classes with this name, there is one per class loader that defined it.
+    pub fn class_ids(&self, name: &str) -> &[u64] {
+        self.class_ids_by_name
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn object(&self, id: u64) -> Option<&SubTag<'a>> {
+        self.objects.get(&id)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &SubTag<'a>> {
+        self.objects.values()
+    }
+
+    /// Name of the heap the object was dumped in, `app`, `image` or `zygote` on ART.
+    pub fn heap_name(&self, id: u64) -> &'a str {
+        self.object_heaps
+            .get(&id)
+            .and_then(|heap_id| self.heap_names.get(heap_id))
+            .copied()
+            .unwrap_or(DEFAULT_HEAP_NAME)
+    }
+
+    pub fn roots(&self) -> &[SubTag<'a>] {
+        &self.roots
+    }
+
+    /// Roots that keep objects alive, i.e. everything but heap markers and `Unreachable`.
+    pub fn gc_roots(&self) -> impl Iterator<Item = &SubTag<'a>> {
+        self.roots
+            .iter()
+            .filter(|root| !matches!(root, SubTag::HeapDumpInfo { .. } | SubTag::Unreachable(_)))
+    }
+
+    pub fn stack_trace(&self, serial_number: u32) -> Option<&StackTrace> {
+        self.stack_traces.get(&serial_number)
+    }
+
+    pub fn stack_frame(&self, id: u64) -> Option<&StackFrame<'a>> {
+        self.stack_frames.get(&id)
+    }
+
+    pub fn super_class(&self, class_id: u64) -> Option<u64> {
+        match self.objects.get(&class_id) {
+            Some(SubTag::ClassDump {
+                super_class_object_id,
+                ..
+            }) if *super_class_object_id != 0 => Some(*super_class_object_id),
+            _ => None,
+        }
+    }
+
+    /// The class itself followed by its super classes, up to `java.lang.Object`.
+    pub fn class_hierarchy(&self, class_id: u64) -> impl Iterator<Item = u64> + '_ {
+        std::iter::successors(Some(class_id), move |id| self.super_class(*id))
+            .filter(move |id| self.objects.contains_key(id))
+    }
+
+    /// Class object of an instance or an object array, `None` for anything else.
+    pub fn class_of(&self, id: u64) -> Option<u64> {
+        match self.objects.get(&id)? {
+            SubTag::InstanceDump {
+                class_object_id, ..
+            } => Some(*class_object_id),
+            SubTag::ObjectArrayDump {
+                array_class_object_id,
+                ..
+            } => Some(*array_class_object_id),
+            _ => None,
+        }
+    }
+
+    /// Class name of an instance or array, or the name of the class itself for a class object.
+    pub fn type_name(&self, id: u64) -> Option<String> {
+        match self.objects.get(&id)? {
+            SubTag::ClassDump {
+                class_object_id, ..
+            } => self.class_name(*class_object_id).map(str::to_string),
+            SubTag::PrimitiveArrayDump { element_type, .. } => {
+                Some(format!("{}[]", element_type.name()))
+            }
+            _ => self
+                .class_of(id)
+                .and_then(|class_id| self.class_name(class_id))
+                .map(str::to_string),
+        }
+    }
+
+    /// Whether `class_id` is `ancestor_name` or extends it.
+    pub fn is_subclass_of(&self, class_id: u64, ancestor_name: &str) -> bool {
+        self.class_hierarchy(class_id)
+            .any(|id| self.class_name(id) == Some(ancestor_name))
+    }
+
+    /// Instances of `class_name` and of its subclasses.
+    pub fn instances_of(&self, class_name: &str) -> Vec<u64> {
+        let class_ids: HashSet<u64> = self
+            .class_names
+            .keys()
+            .filter(|class_id| self.is_subclass_of(**class_id, class_name))
+            .copied()
+            .collect();
+        if class_ids.is_empty() {
+            return Vec::new();
+        }
+        self.objects
+            .values()
+            .filter_map(|subtag| match subtag {
+                SubTag::InstanceDump {
+                    object_id,
+                    class_object_id,
+                    ..
+                } if class_ids.contains(class_object_id) => Some(*object_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// How strongly the `referent` of instances of this class is held,
+    /// [`ReferenceStrength::Strong`] unless it is a `java.lang.ref.Reference` subclass.
+    pub fn reference_strength(&self, class_id: u64) -> ReferenceStrength {
+        self.reference_classes
+            .get(&class_id)
+            .copied()
+            .unwrap_or(ReferenceStrength::Strong)
+    }
+
+    /// Instance fields of an object, the fields of its own class come first
+    /// and those of `java.lang.Object` last.
+    pub fn instance_fields(&self, id: u64) -> Vec<FieldValue<'a>> {
+        let (class_object_id, instance_field_values) = match self.objects.get(&id) {
+            Some(SubTag::InstanceDump {
+                class_object_id,
+                instance_field_values,
+                ..
+            }) => (*class_object_id, *instance_field_values),
+            _ => return Vec::new(),
+        };
+
+        let slice = Slice::new(instance_field_values, self.id_size as u32);
+        let mut fields = Vec::new();
+        for class_id in self.class_hierarchy(class_object_id) {
+            let instant_fields = match self.objects.get(&class_id) {
+                Some(SubTag::ClassDump { instant_fields, .. }) => instant_fields,
+                _ => continue,
+            };
+            for field in instant_fields {
+                let value = match JavaValue::parse(&slice, field.java_type) {
+                    Ok(value) => value,
+                    // truncated instance, keep what could be read
+                    Err(_) => return fields,
+                };
+                fields.push(FieldValue {
+                    class_id,
+                    name: self.field_name(class_id, field.name_string_id),
+                    value,
+                });
+            }
+        }
+        fields
+    }
+
+    fn field_name(&self, class_id: u64, name_string_id: u64) -> &'a str {
+        self.field_names
+            .get(&(class_id, name_string_id))
+            .copied()
+            .or_else(|| self.string(name_string_id))
+            .unwrap_or_default()
+    }
+
+    /// `referent` of a `java.lang.ref.Reference`, a field of another class
+    /// with the same name is not one.
+    fn is_referent_field(&self, field: &FieldValue) -> bool {
+        field.name == "referent" && self.class_name(field.class_id) == Some(REFERENCE_CLASS)
+    }
+
+    /// Object a `java.lang.ref.Reference` points to, `None` for any other
+    /// object or a cleared reference.
+    pub fn referent(&self, id: u64) -> Option<u64> {
+        self.instance_fields(id)
+            .into_iter()
+            .find(|field| self.is_referent_field(field))
+            .and_then(|field| match field.value {
+                JavaValue::Object(referent) if referent != 0 => Some(referent),
+                _ => None,
+            })
+    }
+
+    /// First field called `name`, looking from the most derived class up.
+    pub fn field(&self, id: u64, name: &str) -> Option<JavaValue> {
+        self.instance_fields(id)
+            .into_iter()
+            .find(|field| field.name == name)
+            .map(|field| field.value)
+    }
+
+    pub fn static_fields(&self, class_id: u64) -> Vec<FieldValue<'a>> {
+        match self.objects.get(&class_id) {
+            Some(SubTag::ClassDump { static_fields, .. }) => static_fields
+                .iter()
+                .map(|field| FieldValue {
+                    class_id,
+                    name: self.field_name(class_id, field.name_string_id),
+                    value: field.java_value,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// References going out of an object: object fields of an instance,
+    /// elements of an object array and object static fields of a class.
+    /// Null references and references to objects missing from the dump are left out.
+    pub fn edges(&self, id: u64) -> Vec<Edge<'a>> {
+        let mut edges = Vec::new();
+        match self.objects.get(&id) {
+            Some(SubTag::InstanceDump {
+                class_object_id, ..
+            }) => {
+                let referent_strength = self.reference_strength(*class_object_id);
+                for field in self.instance_fields(id) {
+                    if let JavaValue::Object(target) = field.value {
+                        let strength = if self.is_referent_field(&field) {
+                            referent_strength
+                        } else {
+                            ReferenceStrength::Strong
+                        };
+                        edges.push(Edge {
+                            kind: EdgeKind::InstanceField {
+                                class_id: field.class_id,
+                                name: field.name,
+                            },
+                            strength,
+                            target,
+                        });
+                    }
+                }
+            }
+            Some(SubTag::ObjectArrayDump { elements, .. }) => {
+                for (index, target) in elements.iter().enumerate() {
+                    edges.push(Edge {
+                        kind: EdgeKind::ArrayElement(index),
+                        strength: ReferenceStrength::Strong,
+                        target: *target,
+                    });
+                }
+            }
+            Some(SubTag::ClassDump { .. }) => {
+                for field in self.static_fields(id) {
+                    if let JavaValue::Object(target) = field.value {
+                        edges.push(Edge {
+                            kind: EdgeKind::StaticField {
+                                class_id: field.class_id,
+                                name: field.name,
+                            },
+                            strength: ReferenceStrength::Strong,
+                            target,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+        edges.retain(|edge| self.objects.contains_key(&edge.target));
+        edges
+    }
+
+    /// Content of a `byte[]`, `None` for any other object.
+    pub fn byte_array(&self, id: u64) -> Option<Vec<u8>> {
+        match self.objects.get(&id)? {
+            SubTag::PrimitiveArrayDump {
+                element_type: JavaType::Byte,
+                elements,
+                ..
+            } => Some(
+                elements
+                    .iter()
+                    .map(|e| match e {
+                        JavaValue::Byte(b) => *b as u8,
+                        _ => 0,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Objects referencing any of `targets`, with the reference they hold.
+    /// Walks the whole heap once.
+    pub fn referrers(&self, targets: &HashSet<u64>) -> HashMap<u64, Vec<(u64, EdgeKind<'a>)>> {
+        let mut referrers: HashMap<u64, Vec<(u64, EdgeKind<'a>)>> = HashMap::new();
+        for id in self.objects.keys() {
+            for edge in self.edges(*id) {
+                if targets.contains(&edge.target) {
+                    referrers
+                        .entry(edge.target)
+                        .or_default()
+                        .push((*id, edge.kind));
+                }
+            }
+        }
+        referrers
+    }
+
+    /// Bytes the object occupies by itself. Instances of ART dumps take the
+    /// size of their class from the dump, like Android Studio does, anything
+    /// else is computed with the [`SizeModel`].
+    pub fn shallow_size(&self, id: u64) -> u64 {
+        match self.objects.get(&id) {
+            Some(SubTag::InstanceDump {
+                class_object_id, ..
+            }) => self
+                .instance_sizes
+                .get(class_object_id)
+                .copied()
+                .unwrap_or_else(|| self.size_model.instance_size(0)),
+            Some(SubTag::ObjectArrayDump { elements, .. }) => {
+                self.size_model.object_array_size(elements.len())
+            }
+            Some(SubTag::PrimitiveArrayDump {
+                element_type,
+                elements,
+                ..
+            }) => self
+                .size_model
+                .primitive_array_size(*element_type, elements.len()),
+            Some(SubTag::ClassDump { static_fields, .. }) => {
+                let static_bytes = static_fields
+                    .iter()
+                    .map(|f| self.size_model.type_size(f.java_value.java_type()) as u64)
+                    .sum();
+                let class_instance_size = self
+                    .class_ids("java.lang.Class")
+                    .first()
+                    .and_then(|id| self.instance_sizes.get(id))
+                    .copied()
+                    .unwrap_or(0);
+                self.size_model
+                    .class_size(class_instance_size, static_bytes)
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn total_shallow_size(&self) -> u64 {
+        self.objects.keys().map(|id| self.shallow_size(*id)).sum()
+    }
+
+    fn compute_reference_classes(&mut self) {
+        let mut reference_classes = HashMap::new();
+        for (id, subtag) in &self.objects {
+            if !matches!(subtag, SubTag::ClassDump { .. }) {
+                continue;
+            }
+            // the closest known ancestor wins, Cleaner extends PhantomReference
+            let strength = self.class_hierarchy(*id).find_map(|class_id| {
+                let name = self.class_name(class_id)?;
+                REFERENCE_CLASSES
+                    .iter()
+                    .find(|(reference_class, _)| *reference_class == name)
+                    .map(|(_, strength)| *strength)
+            });
+            if let Some(strength) = strength {
+                reference_classes.insert(*id, strength);
+            }
+        }
+        self.reference_classes = reference_classes;
+    }
+
+    fn compute_instance_sizes(&mut self) {
+        let mut sizes = HashMap::new();
+        for (id, subtag) in &self.objects {
+            let SubTag::ClassDump { instance_size, .. } = subtag else {
+                continue;
+            };
+            if self.art_instance_sizes && *instance_size != 0 {
+                sizes.insert(*id, *instance_size as u64);
+                continue;
+            }
+            let field_bytes: u64 = self
+                .class_hierarchy(*id)
+                .filter_map(|class_id| match self.objects.get(&class_id) {
+                    Some(SubTag::ClassDump { instant_fields, .. }) => Some(instant_fields),
+                    _ => None,
+                })
+                .flatten()
+                .filter(|field| {
+                    let name = self.string(field.name_string_id).unwrap_or_default();
+                    !HEADER_FIELDS.contains(&name)
+                })
+                .map(|field| self.size_model.type_size(field.java_type) as u64)
+                .sum();
+            sizes.insert(*id, self.size_model.instance_size(field_bytes));
+        }
+        self.instance_sizes = sizes;
+    }
+}
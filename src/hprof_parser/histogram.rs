@@ -0,0 +1,236 @@
+use crate::hprof_parser::dominator::DominatorTree;
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::snapshot::SubTag;
+use crate::hprof_parser::synthetic::describe_class;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+/// Totals of the objects of one class, in one heap or in all of them.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ClassStats {
+    pub instance_count: u64,
+    pub shallow_size: u64,
+    /// `None` when the histogram was built without a dominator tree
+    pub retained_size: Option<u64>,
+}
+
+impl ClassStats {
+    fn add(&mut self, shallow_size: u64, retained_size: Option<u64>) {
+        self.instance_count += 1;
+        self.shallow_size += shallow_size;
+        if let Some(retained_size) = retained_size {
+            *self.retained_size.get_or_insert(0) += retained_size;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistogramRow {
+    pub class_name: String,
+    pub total: ClassStats,
+    /// per heap named in HEAP_DUMP_INFO: `app`, `image`, `zygote`
+    pub heaps: BTreeMap<String, ClassStats>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HistogramOrder {
+    InstanceCount,
+    ShallowSize,
+    RetainedSize,
+    ClassName,
+}
+
+/// Instance count, shallow and retained size per class name. Classes with the
+/// same name defined by different class loaders are merged.
+#[derive(Debug, Clone)]
+pub struct ClassHistogram {
+    rows: Vec<HistogramRow>,
+}
+
+impl ClassHistogram {
+    /// Retained sizes are only filled in when `dominators` is given. Objects
+    /// retained by another instance of the same class are not counted twice.
+    pub fn new(graph: &HeapGraph, dominators: Option<&DominatorTree>) -> Self {
+        let nested = dominators
+            .map(|dominators| dominators.dominated_by_same_key(|id| class_key(graph, id)))
+            .unwrap_or_default();
+        let mut rows: HashMap<String, HistogramRow> = HashMap::new();
+        for subtag in graph.objects() {
+            let Some(id) = subtag.object_id() else {
+                continue;
+            };
+            let class_name = match subtag {
+                SubTag::ClassDump { .. } => "java.lang.Class".to_string(),
+                _ => match graph.type_name(id) {
+                    Some(name) => name,
+                    None => continue,
+                },
+            };
+            // already part of the retained size of an object of the same class
+            let retained_size = dominators.map(|dominators| {
+                if nested.contains(&id) {
+                    0
+                } else {
+                    dominators.retained_size(id)
+                }
+            });
+            let shallow_size = graph.shallow_size(id);
+
+            let row = rows
+                .entry(class_name.clone())
+                .or_insert_with(|| HistogramRow {
+                    class_name,
+                    total: ClassStats::default(),
+                    heaps: BTreeMap::new(),
+                });
+            row.total.add(shallow_size, retained_size);
+            row.heaps
+                .entry(graph.heap_name(id).to_string())
+                .or_default()
+                .add(shallow_size, retained_size);
+        }
+
+        let mut histogram = Self {
+            rows: rows.into_values().collect(),
+        };
+        histogram.sort_by(HistogramOrder::ShallowSize);
+        histogram
+    }
+
+    /// Keeps the classes whose name matches `pattern`. `*` matches any run of
+    /// characters and `?` a single one, a pattern without them matches any
+    /// name containing it.
+    pub fn filter(mut self, pattern: &str) -> Self {
+        self.rows
+            .retain(|row| matches_pattern(pattern, &row.class_name));
+        self
+    }
+
+    /// Largest first, or alphabetically for [`HistogramOrder::ClassName`].
+    pub fn sort_by(&mut self, order: HistogramOrder) {
+        match order {
+            HistogramOrder::InstanceCount => self
+                .rows
+                .sort_by_key(|row| std::cmp::Reverse(row.total.instance_count)),
+            HistogramOrder::ShallowSize => self
+                .rows
+                .sort_by_key(|row| std::cmp::Reverse(row.total.shallow_size)),
+            HistogramOrder::RetainedSize => self
+                .rows
+                .sort_by_key(|row| std::cmp::Reverse(row.total.retained_size)),
+            HistogramOrder::ClassName => self.rows.sort_by(|a, b| a.class_name.cmp(&b.class_name)),
+        }
+    }
+
+    pub fn rows(&self) -> &[HistogramRow] {
+        &self.rows
+    }
+
+    pub fn row(&self, class_name: &str) -> Option<&HistogramRow> {
+        self.rows.iter().find(|row| row.class_name == class_name)
+    }
+}
+
+impl Display for ClassHistogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>12} {:>12}  class",
+            "count", "shallow", "retained"
+        )?;
+        for row in &self.rows {
+            let retained = row
+                .total
+                .retained_size
+                .map(|size| size.to_string())
+                .unwrap_or_else(|| "-".to_string());
+            let heaps: Vec<String> = row
+                .heaps
+                .iter()
+                .map(|(heap, stats)| {
+                    format!("{}: {}/{}", heap, stats.instance_count, stats.shallow_size)
+                })
+                .collect();
+            writeln!(
+                f,
+                "{:>10} {:>12} {:>12}  {} [{}]",
+                row.total.instance_count,
+                row.total.shallow_size,
+                retained,
+                describe_class(&row.class_name),
+                heaps.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Cheap identity of the class of an object, without building its name.
+fn class_key(graph: &HeapGraph, id: u64) -> Option<(u8, u64)> {
+    match graph.object(id)? {
+        SubTag::ClassDump { .. } => Some((0, 0)),
+        SubTag::PrimitiveArrayDump { element_type, .. } => Some((1, *element_type as u64)),
+        _ => Some((2, graph.class_of(id)?)),
+    }
+}
+
+/// Glob match with `*` and `?`, or substring match without them.
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return name.contains(pattern);
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substring_without_wildcards() {
+        assert!(matches_pattern("Bitmap", "android.graphics.Bitmap"));
+        assert!(matches_pattern("graphics", "android.graphics.Bitmap"));
+        assert!(matches_pattern("", "anything"));
+        assert!(!matches_pattern("bitmap", "android.graphics.Bitmap"));
+    }
+
+    #[test]
+    fn whole_name_with_wildcards() {
+        assert!(matches_pattern("android.*", "android.graphics.Bitmap"));
+        assert!(!matches_pattern("graphics.*", "android.graphics.Bitmap"));
+        assert!(matches_pattern("*Bitmap", "android.graphics.Bitmap"));
+        assert!(!matches_pattern("*Bitmap", "android.graphics.Bitmap[]"));
+        assert!(matches_pattern("*.*.Bit?ap", "android.graphics.Bitmap"));
+        assert!(matches_pattern("com.foo.*$*", "com.foo.Outer$Inner"));
+        assert!(!matches_pattern("com.foo.*$*", "com.foo.Outer"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("?", ""));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        assert!(matches_pattern("*ab", "aab"));
+        assert!(matches_pattern("a*b*c", "abxbyc"));
+        assert!(!matches_pattern("a*b*c", "abxbyd"));
+        assert!(matches_pattern("**x", "yyx"));
+    }
+}
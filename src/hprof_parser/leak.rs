@@ -0,0 +1,1351 @@
+use crate::hprof_parser::collections::{collection, list_elements};
+use crate::hprof_parser::dominator::DominatorTree;
+use crate::hprof_parser::graph::{HeapGraph, ReferenceStrength};
+use crate::hprof_parser::leak_rules::LeakRules;
+use crate::hprof_parser::library_leaks::{Device, LibraryLeakRule, LibraryLeakRules};
+use crate::hprof_parser::path::{describe_object, simple_class_name, PathFinder, ReferencePath};
+use crate::hprof_parser::snapshot::{JavaValue, SubTag};
+use crate::hprof_parser::synthetic::{captured_objects, describe_class};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+/// An object a detector expects to be garbage, with the reason why.
+#[derive(Debug, Clone)]
+pub struct LeakCandidate {
+    pub object_id: u64,
+    pub reason: String,
+}
+
+/// Looks for objects whose state says they should have been collected,
+/// e.g. an Activity that has been destroyed.
+pub trait LeakDetector {
+    fn name(&self) -> &str;
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate>;
+}
+
+/// A candidate that is still strongly reachable.
+#[derive(Debug, Clone)]
+pub struct Leak<'a> {
+    pub object_id: u64,
+    pub detector: String,
+    pub reason: String,
+    pub retained_size: u64,
+    pub path: ReferencePath<'a>,
+    /// set when the path goes through a reference of the platform or of a
+    /// library known to leak, which the app cannot fix
+    pub library_leak: Option<LibraryLeakRule>,
+}
+
+impl<'a> Leak<'a> {
+    /// See [`ReferencePath::signature`], leaks sharing it most likely have the same cause.
+    pub fn signature(&self, graph: &HeapGraph) -> String {
+        self.path.signature(graph)
+    }
+
+    pub fn display<'l>(&'l self, graph: &'l HeapGraph<'a>) -> LeakDisplay<'l, 'a> {
+        LeakDisplay { leak: self, graph }
+    }
+}
+
+pub struct LeakDisplay<'l, 'a> {
+    leak: &'l Leak<'a>,
+    graph: &'l HeapGraph<'a>,
+}
+
+impl<'l, 'a> Display for LeakDisplay<'l, 'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} ({:#x}) leaked: {}",
+            describe_object(self.graph, self.leak.object_id),
+            self.leak.object_id,
+            self.leak.reason
+        )?;
+        writeln!(f, "Retained size: {} bytes", self.leak.retained_size)?;
+        if let Some(rule) = &self.leak.library_leak {
+            writeln!(
+                f,
+                "Library leak through {}.{}: {}",
+                rule.class, rule.field, rule.description
+            )?;
+        }
+        write!(f, "{}", self.leak.path.display(self.graph))
+    }
+}
+
+/// Leaks with the same signature, reported once.
+#[derive(Debug, Clone)]
+pub struct LeakGroup<'a> {
+    pub signature: String,
+    /// one per leaked object, largest retained size first
+    pub leaks: Vec<Leak<'a>>,
+    pub retained_size: u64,
+}
+
+impl<'a> LeakGroup<'a> {
+    pub fn count(&self) -> usize {
+        self.leaks.len()
+    }
+
+    /// 64 bit FNV-1a hash of the signature, short enough for a bug title and
+    /// stable across runs and builds of this crate.
+    pub fn signature_hash(&self) -> String {
+        let hash = self
+            .signature
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+        format!("{:016x}", hash)
+    }
+
+    pub fn display<'l>(&'l self, graph: &'l HeapGraph<'a>) -> LeakGroupDisplay<'l, 'a> {
+        LeakGroupDisplay { group: self, graph }
+    }
+}
+
+pub struct LeakGroupDisplay<'l, 'a> {
+    group: &'l LeakGroup<'a>,
+    graph: &'l HeapGraph<'a>,
+}
+
+impl<'l, 'a> Display for LeakGroupDisplay<'l, 'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let group = self.group;
+        writeln!(
+            f,
+            "{} leaks retaining {} bytes, signature {}",
+            group.count(),
+            group.retained_size,
+            group.signature_hash()
+        )?;
+        // the largest one stands for the others
+        match group.leaks.first() {
+            Some(leak) => write!(f, "{}", leak.display(self.graph)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Groups leaks by [`Leak::signature`], largest total retained size first.
+/// An object reported by several detectors is only counted once.
+pub fn group_leaks<'a>(graph: &HeapGraph, leaks: Vec<Leak<'a>>) -> Vec<LeakGroup<'a>> {
+    let mut groups: HashMap<String, LeakGroup<'a>> = HashMap::new();
+    let mut seen: HashSet<u64> = HashSet::new();
+    for leak in leaks {
+        if !seen.insert(leak.object_id) {
+            continue;
+        }
+        let signature = leak.signature(graph);
+        let group = groups
+            .entry(signature.clone())
+            .or_insert_with(|| LeakGroup {
+                signature,
+                leaks: Vec::new(),
+                retained_size: 0,
+            });
+        group.retained_size += leak.retained_size;
+        group.leaks.push(leak);
+    }
+
+    let mut groups: Vec<LeakGroup<'a>> = groups.into_values().collect();
+    for group in &mut groups {
+        group
+            .leaks
+            .sort_by_key(|leak| std::cmp::Reverse(leak.retained_size));
+    }
+    groups.sort_by(|a, b| {
+        b.retained_size
+            .cmp(&a.retained_size)
+            .then_with(|| a.signature.cmp(&b.signature))
+    });
+    groups
+}
+
+/// Leak groups split between those the app can fix and library leaks.
+#[derive(Debug, Clone)]
+pub struct LeakReport<'a> {
+    pub app_leaks: Vec<LeakGroup<'a>>,
+    pub library_leaks: Vec<LeakGroup<'a>>,
+}
+
+impl<'a> LeakReport<'a> {
+    pub fn new(graph: &HeapGraph, leaks: Vec<Leak<'a>>) -> Self {
+        // leaks of a group share their path, so they are all tagged the same
+        let (library_leaks, app_leaks) = group_leaks(graph, leaks)
+            .into_iter()
+            .partition(|group| group.leaks[0].library_leak.is_some());
+        Self {
+            app_leaks,
+            library_leaks,
+        }
+    }
+
+    pub fn display<'r>(&'r self, graph: &'r HeapGraph<'a>) -> LeakReportDisplay<'r, 'a> {
+        LeakReportDisplay {
+            report: self,
+            graph,
+        }
+    }
+}
+
+pub struct LeakReportDisplay<'r, 'a> {
+    report: &'r LeakReport<'a>,
+    graph: &'r HeapGraph<'a>,
+}
+
+impl<'r, 'a> Display for LeakReportDisplay<'r, 'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (title, groups) in [
+            ("Application leaks", &self.report.app_leaks),
+            ("Library leaks", &self.report.library_leaks),
+        ] {
+            writeln!(f, "==== {} ({})", title, groups.len())?;
+            for group in groups {
+                writeln!(f)?;
+                write!(f, "{}", group.display(self.graph))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs leak detectors over a dump and keeps the candidates that are still
+/// strongly reachable, along with their retained size and shortest path.
+pub struct LeakFinder<'g, 'a> {
+    graph: &'g HeapGraph<'a>,
+    path_finder: PathFinder<'g, 'a>,
+    detectors: Vec<Box<dyn LeakDetector>>,
+    library_leaks: LibraryLeakRules,
+}
+
+impl<'g, 'a> LeakFinder<'g, 'a> {
+    pub fn new(graph: &'g HeapGraph<'a>) -> Self {
+        Self {
+            graph,
+            path_finder: PathFinder::new(graph),
+            detectors: Vec::new(),
+            library_leaks: LibraryLeakRules::default(),
+        }
+    }
+
+    /// Replaces the default path finder, e.g. to exclude some fields.
+    pub fn path_finder(mut self, path_finder: PathFinder<'g, 'a>) -> Self {
+        self.path_finder = path_finder;
+        self
+    }
+
+    pub fn detector<D: LeakDetector + 'static>(mut self, detector: D) -> Self {
+        self.detectors.push(Box::new(detector));
+        self
+    }
+
+    /// Adds the detectors of the rules and excludes their fields from the paths.
+    pub fn rules(mut self, rules: &LeakRules) -> Self {
+        for exclusion in &rules.exclusions {
+            self.path_finder = self
+                .path_finder
+                .exclude_field(&exclusion.class, &exclusion.field);
+        }
+        for detector in &rules.detectors {
+            self.detectors.push(Box::new(detector.clone()));
+        }
+        self
+    }
+
+    /// Tags leaks matching one of the rules as library leaks, rules that do
+    /// not apply to the device of the dump are left out.
+    pub fn library_leaks(mut self, rules: &LibraryLeakRules) -> Self {
+        let device = Device::from_graph(self.graph);
+        self.library_leaks = self.library_leaks.extend(rules.for_device(&device));
+        self
+    }
+
+    /// Adds every built-in android detector.
+    pub fn android_detectors(self) -> Self {
+        self.detector(ActivityLeakDetector)
+            .detector(FragmentLeakDetector)
+            .detector(DetachedViewLeakDetector)
+            .detector(WindowLeakDetector)
+            .detector(StaticContextLeakDetector)
+            .detector(HandlerLeakDetector)
+    }
+
+    /// Leaks sorted by retained size, largest first.
+    pub fn find(&self, dominators: &DominatorTree) -> Vec<Leak<'a>> {
+        // an object found by several detectors is reported once, by the first
+        let mut candidates = Vec::new();
+        let mut seen: HashSet<u64> = HashSet::new();
+        for detector in &self.detectors {
+            for candidate in detector.detect(self.graph) {
+                if dominators.is_reachable(candidate.object_id) && seen.insert(candidate.object_id)
+                {
+                    candidates.push((detector.name().to_string(), candidate));
+                }
+            }
+        }
+
+        let targets: Vec<u64> = candidates.iter().map(|(_, c)| c.object_id).collect();
+        let mut paths = self.path_finder.shortest_paths(&targets);
+
+        let mut leaks: Vec<Leak<'a>> = candidates
+            .into_iter()
+            .filter_map(|(detector, candidate)| {
+                // a path excluded by the path finder means the candidate is not a leak
+                let path = paths.remove(&candidate.object_id)?;
+                let library_leak = self.library_leaks.find_match(self.graph, &path).cloned();
+                Some(Leak {
+                    object_id: candidate.object_id,
+                    detector,
+                    reason: candidate.reason,
+                    retained_size: dominators.retained_size(candidate.object_id),
+                    path,
+                    library_leak,
+                })
+            })
+            .collect();
+        leaks.sort_by_key(|leak| std::cmp::Reverse(leak.retained_size));
+        leaks
+    }
+
+    /// [`Self::find`] with the leaks grouped by signature, see [`group_leaks`].
+    pub fn find_grouped(&self, dominators: &DominatorTree) -> Vec<LeakGroup<'a>> {
+        group_leaks(self.graph, self.find(dominators))
+    }
+
+    /// [`Self::find`] with app and library leaks apart, see [`LeakReport`].
+    pub fn report(&self, dominators: &DominatorTree) -> LeakReport<'a> {
+        LeakReport::new(self.graph, self.find(dominators))
+    }
+}
+
+/// `android.app.Activity` instances that have been destroyed or finished.
+pub struct ActivityLeakDetector;
+
+impl LeakDetector for ActivityLeakDetector {
+    fn name(&self) -> &str {
+        "activity"
+    }
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate> {
+        graph
+            .instances_of("android.app.Activity")
+            .into_iter()
+            .filter_map(|id| {
+                let reason = destroyed_activity_reason(graph, id)?;
+                Some(LeakCandidate {
+                    object_id: id,
+                    reason: reason.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Fragments that are no longer attached to a `FragmentManager`.
+pub struct FragmentLeakDetector;
+
+const FRAGMENT_CLASSES: [&str; 3] = [
+    "androidx.fragment.app.Fragment",
+    "android.support.v4.app.Fragment",
+    "android.app.Fragment",
+];
+
+impl LeakDetector for FragmentLeakDetector {
+    fn name(&self) -> &str {
+        "fragment"
+    }
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate> {
+        FRAGMENT_CLASSES
+            .iter()
+            .flat_map(|class_name| graph.instances_of(class_name))
+            .filter(|id| graph.field(*id, "mFragmentManager") == Some(JavaValue::Object(0)))
+            .map(|id| LeakCandidate {
+                object_id: id,
+                reason: "Fragment#mFragmentManager is null".to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Detached `android.view.View` trees whose Activity has been destroyed,
+/// reported once per root view. Detached views of a live Activity, such as
+/// recycled list items or pages off screen, are expected.
+pub struct DetachedViewLeakDetector;
+
+impl LeakDetector for DetachedViewLeakDetector {
+    fn name(&self) -> &str {
+        "detached-view"
+    }
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate> {
+        let mut trees: HashMap<u64, usize> = HashMap::new();
+        for id in graph.instances_of("android.view.View") {
+            if !is_detached_view(graph, id) {
+                continue;
+            }
+            // climb to the topmost detached view of the tree
+            let mut root = id;
+            let mut climbed = vec![id];
+            while let Some(JavaValue::Object(parent)) = graph.field(root, "mParent") {
+                if !is_detached_view(graph, parent) {
+                    break;
+                }
+                // a corrupt dump can have views that are their own ancestors,
+                // the smallest id of the cycle is its root from any start
+                if let Some(start) = climbed.iter().position(|view| *view == parent) {
+                    root = climbed[start..].iter().copied().min().unwrap_or(parent);
+                    break;
+                }
+                climbed.push(parent);
+                root = parent;
+            }
+            *trees.entry(root).or_default() += 1;
+        }
+
+        let mut candidates: Vec<LeakCandidate> = trees
+            .into_iter()
+            .filter_map(|(root, views)| {
+                let Some(JavaValue::Object(context)) = graph.field(root, "mContext") else {
+                    return None;
+                };
+                let activity = unwrap_activity(graph, context)?;
+                let reason = destroyed_activity_reason(graph, activity)?;
+                Some(LeakCandidate {
+                    object_id: root,
+                    reason: format!(
+                        "View#mAttachInfo is null and {}, {} detached views in the tree",
+                        reason, views
+                    ),
+                })
+            })
+            .collect();
+        candidates.sort_by_key(|candidate| candidate.object_id);
+        candidates
+    }
+}
+
+/// Root views still registered in `WindowManagerGlobal.mViews` whose Activity is gone,
+/// typically dialogs and popup windows that were never dismissed.
+pub struct WindowLeakDetector;
+
+impl LeakDetector for WindowLeakDetector {
+    fn name(&self) -> &str {
+        "window"
+    }
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate> {
+        let mut candidates = Vec::new();
+        for global in graph.instances_of("android.view.WindowManagerGlobal") {
+            let Some(JavaValue::Object(views)) = graph.field(global, "mViews") else {
+                continue;
+            };
+            for view in list_elements(graph, views) {
+                let Some(JavaValue::Object(context)) = graph.field(view, "mContext") else {
+                    continue;
+                };
+                let Some(activity) = unwrap_activity(graph, context) else {
+                    continue;
+                };
+                if destroyed_activity_reason(graph, activity).is_some() {
+                    candidates.push(LeakCandidate {
+                        object_id: view,
+                        reason: format!(
+                            "window still in WindowManagerGlobal.mViews after {} was destroyed",
+                            graph.type_name(activity).unwrap_or_default()
+                        ),
+                    });
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// Packages of the platform, whose static fields are not the app's doing,
+/// e.g. `ActivityThread` holds every live Activity.
+const PLATFORM_PACKAGES: [&str; 8] = [
+    "java.",
+    "javax.",
+    "jdk.",
+    "sun.",
+    "android.",
+    "com.android.",
+    "dalvik.",
+    "libcore.",
+];
+
+/// Platform singletons that reference every live Activity or Service, going
+/// through them would make any static reaching them look like a leak.
+const GLOBAL_HOLDERS: [&str; 3] = [
+    "android.app.ActivityThread",
+    "android.view.WindowManagerGlobal",
+    "android.app.LoadedApk",
+];
+
+/// Destroyed Activities and stopped Services strongly reachable from a
+/// static field of an app class, directly or through anything: Views,
+/// Drawables, Context wrappers and collections of the platform included. The
+/// Application and the [`GLOBAL_HOLDERS`] are not gone through, they hold
+/// every live Activity and Service.
+pub struct StaticContextLeakDetector;
+
+impl LeakDetector for StaticContextLeakDetector {
+    fn name(&self) -> &str {
+        "static-context"
+    }
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate> {
+        // breadth first from every app static field, remembering which field
+        // reached each object first
+        let mut fields: Vec<String> = Vec::new();
+        let mut reached_from: HashMap<u64, usize> = HashMap::new();
+        let mut queue: VecDeque<u64> = VecDeque::new();
+        for subtag in graph.objects() {
+            let SubTag::ClassDump {
+                class_object_id, ..
+            } = subtag
+            else {
+                continue;
+            };
+            let class_name = graph.class_name(*class_object_id).unwrap_or_default();
+            if is_platform_class(class_name) {
+                continue;
+            }
+            for field in graph.static_fields(*class_object_id) {
+                let JavaValue::Object(target) = field.value else {
+                    continue;
+                };
+                if target != 0 && !reached_from.contains_key(&target) {
+                    reached_from.insert(target, fields.len());
+                    fields.push(format!("{}.{}", class_name, field.name));
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        let running_services = running_services(graph);
+        let mut classes: HashMap<u64, ContextClass> = HashMap::new();
+        let mut candidates = Vec::new();
+        while let Some(id) = queue.pop_front() {
+            let field = reached_from[&id];
+            let class = match graph.class_of(id) {
+                Some(class_id) => *classes
+                    .entry(class_id)
+                    .or_insert_with(|| ContextClass::of(graph, class_id)),
+                None => ContextClass::Other,
+            };
+            let reason = match class {
+                ContextClass::Activity => destroyed_activity_reason(graph, id),
+                ContextClass::Service => running_services
+                    .as_ref()
+                    .is_some_and(|running| !running.contains(&id))
+                    .then_some("Service is not in ActivityThread#mServices"),
+                _ => None,
+            };
+            if let Some(reason) = reason {
+                candidates.push(LeakCandidate {
+                    object_id: id,
+                    reason: format!(
+                        "{} reachable from static field {} and {}",
+                        simple_class_name(&graph.type_name(id).unwrap_or_default()),
+                        fields[field],
+                        reason
+                    ),
+                });
+            }
+
+            let targets: Vec<u64> = match class {
+                ContextClass::Application => continue,
+                // only the context it wraps
+                ContextClass::PlatformWrapper => match graph.field(id, "mBase") {
+                    Some(JavaValue::Object(base)) => vec![base],
+                    _ => continue,
+                },
+                ContextClass::GlobalHolder => continue,
+                _ => graph
+                    .edges(id)
+                    .into_iter()
+                    .filter(|edge| edge.strength == ReferenceStrength::Strong)
+                    .map(|edge| edge.target)
+                    .collect(),
+            };
+            for target in targets {
+                if target != 0 && !reached_from.contains_key(&target) {
+                    reached_from.insert(target, field);
+                    queue.push_back(target);
+                }
+            }
+        }
+        candidates.sort_by_key(|candidate| candidate.object_id);
+        candidates
+    }
+}
+
+/// What [`StaticContextLeakDetector`] does with instances of a class.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ContextClass {
+    Activity,
+    Service,
+    Application,
+    /// a `ContextWrapper` of the platform, e.g. `ContextThemeWrapper`
+    PlatformWrapper,
+    /// one of [`GLOBAL_HOLDERS`]
+    GlobalHolder,
+    Other,
+}
+
+impl ContextClass {
+    fn of(graph: &HeapGraph, class_id: u64) -> Self {
+        let class_name = graph.class_name(class_id).unwrap_or_default();
+        if graph.is_subclass_of(class_id, "android.app.Activity") {
+            ContextClass::Activity
+        } else if graph.is_subclass_of(class_id, "android.app.Service") {
+            ContextClass::Service
+        } else if graph.is_subclass_of(class_id, "android.app.Application") {
+            ContextClass::Application
+        } else if GLOBAL_HOLDERS
+            .iter()
+            .any(|holder| graph.is_subclass_of(class_id, holder))
+        {
+            ContextClass::GlobalHolder
+        } else if is_platform_class(class_name)
+            && graph.is_subclass_of(class_id, "android.content.ContextWrapper")
+        {
+            ContextClass::PlatformWrapper
+        } else {
+            ContextClass::Other
+        }
+    }
+}
+
+fn is_platform_class(class_name: &str) -> bool {
+    PLATFORM_PACKAGES
+        .iter()
+        .any(|package| class_name.starts_with(package))
+}
+
+/// Services of `ActivityThread.mServices`, `None` when the dump has no
+/// ActivityThread to tell which Services are running.
+fn running_services(graph: &HeapGraph) -> Option<HashSet<u64>> {
+    let mut running = None;
+    for thread in graph.instances_of("android.app.ActivityThread") {
+        let Some(JavaValue::Object(services)) = graph.field(thread, "mServices") else {
+            continue;
+        };
+        let services = collection(graph, services)?;
+        running
+            .get_or_insert_with(HashSet::new)
+            .extend(services.entries.iter().map(|(_, service)| *service));
+    }
+    running
+}
+
+/// `android.os.Message` instances still queued in a `MessageQueue` whose
+/// target Handler or callback is an inner class or lambda capturing a
+/// destroyed Activity.
+pub struct HandlerLeakDetector;
+
+impl LeakDetector for HandlerLeakDetector {
+    fn name(&self) -> &str {
+        "handler"
+    }
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate> {
+        let mut candidates = Vec::new();
+        for queue in graph.instances_of("android.os.MessageQueue") {
+            let mut seen = HashSet::new();
+            let mut message = match graph.field(queue, "mMessages") {
+                Some(JavaValue::Object(message)) => message,
+                _ => 0,
+            };
+            while message != 0 && seen.insert(message) {
+                for field in ["target", "callback"] {
+                    let Some(JavaValue::Object(holder)) = graph.field(message, field) else {
+                        continue;
+                    };
+                    let activity = captured_objects(graph, holder)
+                        .into_iter()
+                        .filter_map(|outer| unwrap_activity(graph, outer))
+                        .find(|activity| destroyed_activity_reason(graph, *activity).is_some());
+                    if let Some(activity) = activity {
+                        candidates.push(LeakCandidate {
+                            object_id: message,
+                            reason: format!(
+                                "Message#{} is {}, which captures destroyed {}",
+                                field,
+                                describe_class(&graph.type_name(holder).unwrap_or_default()),
+                                graph.type_name(activity).unwrap_or_default()
+                            ),
+                        });
+                        break;
+                    }
+                }
+                message = match graph.field(message, "next") {
+                    Some(JavaValue::Object(next)) => next,
+                    _ => 0,
+                };
+            }
+        }
+        candidates
+    }
+}
+
+/// Follows `ContextWrapper.mBase` until an Activity is found.
+pub fn unwrap_activity(graph: &HeapGraph, context: u64) -> Option<u64> {
+    let mut id = context;
+    // wrappers are never nested deeply, the bound protects against cycles
+    for _ in 0..16 {
+        let class_id = graph.class_of(id)?;
+        if graph.is_subclass_of(class_id, "android.app.Activity") {
+            return Some(id);
+        }
+        if !graph.is_subclass_of(class_id, "android.content.ContextWrapper") {
+            return None;
+        }
+        match graph.field(id, "mBase") {
+            Some(JavaValue::Object(base)) if base != 0 => id = base,
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Why an Activity counts as destroyed, `None` if it is still alive.
+pub fn destroyed_activity_reason(graph: &HeapGraph, activity: u64) -> Option<&'static str> {
+    if graph.field(activity, "mDestroyed") == Some(JavaValue::Boolean(true)) {
+        Some("Activity#mDestroyed is true")
+    } else if graph.field(activity, "mFinished") == Some(JavaValue::Boolean(true)) {
+        Some("Activity#mFinished is true")
+    } else {
+        None
+    }
+}
+
+fn is_detached_view(graph: &HeapGraph, id: u64) -> bool {
+    graph
+        .class_of(id)
+        .is_some_and(|class_id| graph.is_subclass_of(class_id, "android.view.View"))
+        && graph.field(id, "mAttachInfo") == Some(JavaValue::Object(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::JavaType;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    /// `com.example.MainActivity`, an `android.app.Activity` with an `mData` byte array.
+    fn activity_class(dump: &mut DumpBuilder) -> u64 {
+        let activity = dump.class(
+            "android.app.Activity",
+            0,
+            &[
+                ("mDestroyed", JavaType::Boolean),
+                ("mFinished", JavaType::Boolean),
+            ],
+        );
+        dump.class(
+            "com.example.MainActivity",
+            activity,
+            &[("mData", JavaType::Object)],
+        )
+    }
+
+    fn activity(dump: &mut DumpBuilder, class_id: u64, destroyed: bool) -> u64 {
+        let data = dump.primitive_array(JavaType::Byte, &[JavaValue::Byte(0); 64]);
+        dump.instance(
+            class_id,
+            &[
+                ("mDestroyed", JavaValue::Boolean(destroyed)),
+                ("mData", JavaValue::Object(data)),
+            ],
+        )
+    }
+
+    /// `com.example.Cache`, with its static fields set to `values` and kept by a sticky class root.
+    fn static_holder(dump: &mut DumpBuilder, values: &[(&str, JavaValue)]) -> u64 {
+        let holder = dump.class_with_statics("com.example.Cache", 0, &[], values);
+        dump.sticky_class(holder);
+        holder
+    }
+
+    fn find<D: LeakDetector + 'static>(bytes: &[u8], detector: D) -> Vec<u64> {
+        let graph = HeapGraph::from_bytes(bytes).unwrap();
+        let dominators = DominatorTree::new(&graph);
+        LeakFinder::new(&graph)
+            .detector(detector)
+            .find(&dominators)
+            .iter()
+            .map(|leak| leak.object_id)
+            .collect()
+    }
+
+    #[test]
+    fn destroyed_activity() {
+        let mut dump = DumpBuilder::new();
+        let class_id = activity_class(&mut dump);
+        let destroyed = activity(&mut dump, class_id, true);
+        let live = activity(&mut dump, class_id, false);
+        let unreachable = activity(&mut dump, class_id, true);
+        let holder = static_holder(
+            &mut dump,
+            &[
+                ("sDestroyed", JavaValue::Object(destroyed)),
+                ("sLive", JavaValue::Object(live)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let dominators = DominatorTree::new(&graph);
+
+        let candidates: Vec<u64> = ActivityLeakDetector
+            .detect(&graph)
+            .iter()
+            .map(|candidate| candidate.object_id)
+            .collect();
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates.contains(&destroyed) && candidates.contains(&unreachable));
+
+        let leaks = LeakFinder::new(&graph)
+            .detector(ActivityLeakDetector)
+            .find(&dominators);
+        assert_eq!(leaks.len(), 1);
+        let leak = &leaks[0];
+        assert_eq!(leak.object_id, destroyed);
+        assert_eq!(leak.detector, "activity");
+        assert_eq!(leak.reason, "Activity#mDestroyed is true");
+        let data = match graph.field(destroyed, "mData") {
+            Some(JavaValue::Object(data)) => data,
+            _ => panic!("mData is not set"),
+        };
+        assert_eq!(
+            leak.retained_size,
+            graph.shallow_size(destroyed) + graph.shallow_size(data)
+        );
+        assert_eq!(leak.path.root_name, "System class");
+        let steps: Vec<u64> = leak.path.steps.iter().map(|step| step.object_id).collect();
+        assert_eq!(steps, vec![holder, destroyed]);
+        assert_eq!(
+            leak.signature(&graph),
+            "System class > static com.example.Cache.sDestroyed > com.example.MainActivity"
+        );
+    }
+
+    #[test]
+    fn finished_activity() {
+        let mut dump = DumpBuilder::new();
+        let class_id = activity_class(&mut dump);
+        let finished = dump.instance(class_id, &[("mFinished", JavaValue::Boolean(true))]);
+        static_holder(&mut dump, &[("sActivity", JavaValue::Object(finished))]);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let candidates = ActivityLeakDetector.detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].reason, "Activity#mFinished is true");
+    }
+
+    #[test]
+    fn detached_fragment() {
+        let mut dump = DumpBuilder::new();
+        let fragment_class = dump.class(
+            "androidx.fragment.app.Fragment",
+            0,
+            &[("mFragmentManager", JavaType::Object)],
+        );
+        let manager_class = dump.class("androidx.fragment.app.FragmentManager", 0, &[]);
+        let manager = dump.instance(manager_class, &[]);
+        let detached = dump.instance(fragment_class, &[]);
+        let attached = dump.instance(
+            fragment_class,
+            &[("mFragmentManager", JavaValue::Object(manager))],
+        );
+        let unreachable = dump.instance(fragment_class, &[]);
+        static_holder(
+            &mut dump,
+            &[
+                ("sDetached", JavaValue::Object(detached)),
+                ("sAttached", JavaValue::Object(attached)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let mut candidates: Vec<u64> = FragmentLeakDetector
+            .detect(&graph)
+            .iter()
+            .map(|candidate| candidate.object_id)
+            .collect();
+        candidates.sort();
+        assert_eq!(candidates, vec![detached, unreachable]);
+        assert_eq!(find(&bytes, FragmentLeakDetector), vec![detached]);
+    }
+
+    #[test]
+    fn leaks_sorted_by_retained_size() {
+        let mut dump = DumpBuilder::new();
+        let class_id = activity_class(&mut dump);
+        let small = dump.instance(class_id, &[("mDestroyed", JavaValue::Boolean(true))]);
+        let large = activity(&mut dump, class_id, true);
+        static_holder(
+            &mut dump,
+            &[
+                ("sSmall", JavaValue::Object(small)),
+                ("sLarge", JavaValue::Object(large)),
+            ],
+        );
+        let bytes = dump.build();
+
+        assert_eq!(find(&bytes, ActivityLeakDetector), vec![large, small]);
+    }
+
+    /// `android.view.View` and a `android.content.ContextWrapper` around contexts.
+    fn view_classes(dump: &mut DumpBuilder) -> (u64, u64) {
+        let view = dump.class(
+            "android.view.View",
+            0,
+            &[
+                ("mParent", JavaType::Object),
+                ("mAttachInfo", JavaType::Object),
+                ("mContext", JavaType::Object),
+            ],
+        );
+        let wrapper = dump.class(
+            "android.content.ContextWrapper",
+            0,
+            &[("mBase", JavaType::Object)],
+        );
+        (view, wrapper)
+    }
+
+    fn view(dump: &mut DumpBuilder, class_id: u64, parent: u64, context: u64) -> u64 {
+        dump.instance(
+            class_id,
+            &[
+                ("mParent", JavaValue::Object(parent)),
+                ("mContext", JavaValue::Object(context)),
+            ],
+        )
+    }
+
+    #[test]
+    fn detached_view_trees() {
+        let mut dump = DumpBuilder::new();
+        let activity_class = activity_class(&mut dump);
+        let (view_class, wrapper_class) = view_classes(&mut dump);
+        let destroyed = activity(&mut dump, activity_class, true);
+        let live = activity(&mut dump, activity_class, false);
+        let wrapper = dump.instance(wrapper_class, &[("mBase", JavaValue::Object(destroyed))]);
+        let root = view(&mut dump, view_class, 0, wrapper);
+        let child = view(&mut dump, view_class, root, wrapper);
+        let live_view = view(&mut dump, view_class, 0, live);
+        static_holder(
+            &mut dump,
+            &[
+                ("sChild", JavaValue::Object(child)),
+                ("sLiveView", JavaValue::Object(live_view)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let candidates = DetachedViewLeakDetector.detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_id, root);
+        assert_eq!(
+            candidates[0].reason,
+            "View#mAttachInfo is null and Activity#mDestroyed is true, 2 detached views in the tree"
+        );
+        assert_eq!(find(&bytes, DetachedViewLeakDetector), vec![root]);
+    }
+
+    #[test]
+    fn detached_view_parent_cycle() {
+        let mut dump = DumpBuilder::new();
+        let activity_class = activity_class(&mut dump);
+        let (view_class, _) = view_classes(&mut dump);
+        let destroyed = activity(&mut dump, activity_class, true);
+        let first = dump.new_id();
+        let second = view(&mut dump, view_class, first, destroyed);
+        dump.instance_with_id(
+            first,
+            view_class,
+            &[
+                ("mParent", JavaValue::Object(second)),
+                ("mContext", JavaValue::Object(destroyed)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let candidates = DetachedViewLeakDetector.detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_id, first.min(second));
+        assert!(candidates[0]
+            .reason
+            .ends_with("2 detached views in the tree"));
+    }
+
+    #[test]
+    fn window_of_destroyed_activity() {
+        let mut dump = DumpBuilder::new();
+        let activity_class = activity_class(&mut dump);
+        let (view_class, wrapper_class) = view_classes(&mut dump);
+        let destroyed = activity(&mut dump, activity_class, true);
+        let live = activity(&mut dump, activity_class, false);
+        let wrapper = dump.instance(wrapper_class, &[("mBase", JavaValue::Object(destroyed))]);
+        let attach_info_class = dump.class("android.view.View$AttachInfo", 0, &[]);
+        let attach_info = dump.instance(attach_info_class, &[]);
+        let mut windows = Vec::new();
+        for context in [wrapper, live] {
+            windows.push(dump.instance(
+                view_class,
+                &[
+                    ("mAttachInfo", JavaValue::Object(attach_info)),
+                    ("mContext", JavaValue::Object(context)),
+                ],
+            ));
+        }
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let array = dump.object_array(array_class, &windows);
+        let list_class = dump.class(
+            "java.util.ArrayList",
+            0,
+            &[("elementData", JavaType::Object), ("size", JavaType::Int)],
+        );
+        let list = dump.instance(
+            list_class,
+            &[
+                ("elementData", JavaValue::Object(array)),
+                ("size", JavaValue::Int(2)),
+            ],
+        );
+        let global_class = dump.class(
+            "android.view.WindowManagerGlobal",
+            0,
+            &[("mViews", JavaType::Object)],
+        );
+        let global = dump.instance(global_class, &[("mViews", JavaValue::Object(list))]);
+        dump.root(global);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        assert!(DetachedViewLeakDetector.detect(&graph).is_empty());
+        let candidates = WindowLeakDetector.detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_id, windows[0]);
+        assert_eq!(
+            candidates[0].reason,
+            "window still in WindowManagerGlobal.mViews after com.example.MainActivity was destroyed"
+        );
+        assert_eq!(find(&bytes, WindowLeakDetector), vec![windows[0]]);
+    }
+
+    fn static_context_leaks(graph: &HeapGraph) -> Vec<u64> {
+        StaticContextLeakDetector
+            .detect(graph)
+            .iter()
+            .map(|candidate| candidate.object_id)
+            .collect()
+    }
+
+    #[test]
+    fn activity_held_by_static() {
+        let mut dump = DumpBuilder::new();
+        let class_id = activity_class(&mut dump);
+        let destroyed = activity(&mut dump, class_id, true);
+        let live = activity(&mut dump, class_id, false);
+        static_holder(
+            &mut dump,
+            &[
+                ("sActivity", JavaValue::Object(destroyed)),
+                ("sLive", JavaValue::Object(live)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let candidates = StaticContextLeakDetector.detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_id, destroyed);
+        assert_eq!(
+            candidates[0].reason,
+            "MainActivity reachable from static field com.example.Cache.sActivity and Activity#mDestroyed is true"
+        );
+        assert_eq!(find(&bytes, StaticContextLeakDetector), vec![destroyed]);
+    }
+
+    /// Views, Drawables and collections of the platform are gone through.
+    #[test]
+    fn activity_held_through_platform_objects() {
+        let mut dump = DumpBuilder::new();
+        let class_id = activity_class(&mut dump);
+        let (view_class, _) = view_classes(&mut dump);
+        let text_view_class = dump.class("android.widget.TextView", view_class, &[]);
+        let drawable_class = dump.class(
+            "android.graphics.drawable.Drawable",
+            0,
+            &[("mCallback", JavaType::Object)],
+        );
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let map_class = dump.class(
+            "android.util.ArrayMap",
+            0,
+            &[("mArray", JavaType::Object), ("mSize", JavaType::Int)],
+        );
+        let activities: Vec<u64> = (0..3)
+            .map(|_| activity(&mut dump, class_id, true))
+            .collect();
+        let text_view = view(&mut dump, text_view_class, 0, activities[0]);
+        let drawable_view = view(&mut dump, view_class, 0, activities[1]);
+        let drawable = dump.instance(
+            drawable_class,
+            &[("mCallback", JavaValue::Object(drawable_view))],
+        );
+        let key = dump.instance(array_class, &[]);
+        let array = dump.object_array(array_class, &[key, activities[2]]);
+        let map = dump.instance(
+            map_class,
+            &[
+                ("mArray", JavaValue::Object(array)),
+                ("mSize", JavaValue::Int(1)),
+            ],
+        );
+        static_holder(
+            &mut dump,
+            &[
+                ("sTextView", JavaValue::Object(text_view)),
+                ("sDrawable", JavaValue::Object(drawable)),
+                ("sActivities", JavaValue::Object(map)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        assert_eq!(static_context_leaks(&graph), activities);
+        let reasons: Vec<String> = StaticContextLeakDetector
+            .detect(&graph)
+            .into_iter()
+            .map(|candidate| candidate.reason)
+            .collect();
+        assert!(reasons[0].contains("static field com.example.Cache.sTextView"));
+        assert!(reasons[1].contains("static field com.example.Cache.sDrawable"));
+        assert!(reasons[2].contains("static field com.example.Cache.sActivities"));
+    }
+
+    /// What the Application and the global holders of the platform reference
+    /// is not the static's doing.
+    #[test]
+    fn global_holders_are_not_gone_through() {
+        let mut dump = DumpBuilder::new();
+        let class_id = activity_class(&mut dump);
+        let application_class = dump.class(
+            "android.app.Application",
+            0,
+            &[("mActivity", JavaType::Object)],
+        );
+        let thread_class = dump.class(
+            "android.app.ActivityThread",
+            0,
+            &[("mActivity", JavaType::Object)],
+        );
+        let context_class = dump.class(
+            "android.app.ContextImpl",
+            0,
+            &[("mMainThread", JavaType::Object)],
+        );
+        let destroyed = [
+            activity(&mut dump, class_id, true),
+            activity(&mut dump, class_id, true),
+        ];
+        let application = dump.instance(
+            application_class,
+            &[("mActivity", JavaValue::Object(destroyed[0]))],
+        );
+        let thread = dump.instance(
+            thread_class,
+            &[("mActivity", JavaValue::Object(destroyed[1]))],
+        );
+        let context = dump.instance(context_class, &[("mMainThread", JavaValue::Object(thread))]);
+        static_holder(
+            &mut dump,
+            &[
+                ("sApplication", JavaValue::Object(application)),
+                ("sContext", JavaValue::Object(context)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        assert!(static_context_leaks(&graph).is_empty());
+    }
+
+    #[test]
+    fn stopped_service() {
+        let mut dump = DumpBuilder::new();
+        let service_class = dump.class("android.app.Service", 0, &[]);
+        let service_class = dump.class("com.example.SyncService", service_class, &[]);
+        let running = dump.instance(service_class, &[]);
+        let stopped = dump.instance(service_class, &[]);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let map_class = dump.class(
+            "android.util.ArrayMap",
+            0,
+            &[("mArray", JavaType::Object), ("mSize", JavaType::Int)],
+        );
+        let thread_class = dump.class(
+            "android.app.ActivityThread",
+            0,
+            &[("mServices", JavaType::Object)],
+        );
+        let token = dump.instance(array_class, &[]);
+        let array = dump.object_array(array_class, &[token, running]);
+        let services = dump.instance(
+            map_class,
+            &[
+                ("mArray", JavaValue::Object(array)),
+                ("mSize", JavaValue::Int(1)),
+            ],
+        );
+        let thread = dump.instance(thread_class, &[("mServices", JavaValue::Object(services))]);
+        dump.root(thread);
+        static_holder(
+            &mut dump,
+            &[
+                ("sRunning", JavaValue::Object(running)),
+                ("sStopped", JavaValue::Object(stopped)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let candidates = StaticContextLeakDetector.detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_id, stopped);
+        assert_eq!(
+            candidates[0].reason,
+            "SyncService reachable from static field com.example.Cache.sStopped and Service is not in ActivityThread#mServices"
+        );
+    }
+
+    #[test]
+    fn message_to_anonymous_handler() {
+        let mut dump = DumpBuilder::new();
+        let class_id = activity_class(&mut dump);
+        let handler_class = dump.class("android.os.Handler", 0, &[]);
+        let anonymous_class = dump.class(
+            "com.example.MainActivity$1",
+            handler_class,
+            &[("this$0", JavaType::Object)],
+        );
+        let message_class = dump.class(
+            "android.os.Message",
+            0,
+            &[
+                ("target", JavaType::Object),
+                ("callback", JavaType::Object),
+                ("next", JavaType::Object),
+            ],
+        );
+        let queue_class = dump.class(
+            "android.os.MessageQueue",
+            0,
+            &[("mMessages", JavaType::Object)],
+        );
+        let destroyed = activity(&mut dump, class_id, true);
+        let live = activity(&mut dump, class_id, false);
+        let leaking_handler =
+            dump.instance(anonymous_class, &[("this$0", JavaValue::Object(destroyed))]);
+        let live_handler = dump.instance(anonymous_class, &[("this$0", JavaValue::Object(live))]);
+        let plain_handler = dump.instance(handler_class, &[]);
+        let mut next = 0;
+        let mut messages = Vec::new();
+        for target in [plain_handler, leaking_handler, live_handler] {
+            next = dump.instance(
+                message_class,
+                &[
+                    ("target", JavaValue::Object(target)),
+                    ("next", JavaValue::Object(next)),
+                ],
+            );
+            messages.push(next);
+        }
+        let queue = dump.instance(queue_class, &[("mMessages", JavaValue::Object(next))]);
+        dump.root(queue);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let candidates = HandlerLeakDetector.detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_id, messages[1]);
+        assert!(candidates[0]
+            .reason
+            .starts_with("Message#target is com.example.MainActivity$1 ("));
+        assert!(candidates[0]
+            .reason
+            .ends_with("which captures destroyed com.example.MainActivity"));
+        assert_eq!(find(&bytes, HandlerLeakDetector), vec![messages[1]]);
+    }
+
+    #[test]
+    fn leaks_grouped_by_signature() {
+        let mut dump = DumpBuilder::new();
+        let model = dump.class("com.example.Model", 0, &[]);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let first = dump.instance(model, &[]);
+        let second = dump.instance(model, &[]);
+        let other = dump.instance(model, &[]);
+        let array = dump.object_array(array_class, &[first, 0, second]);
+        static_holder(
+            &mut dump,
+            &[
+                ("sModels", JavaValue::Object(array)),
+                ("sOther", JavaValue::Object(other)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let paths = PathFinder::new(&graph).shortest_paths(&[first, second, other]);
+        let leak = |object_id: u64, detector: &str, retained_size: u64| Leak {
+            object_id,
+            detector: detector.to_string(),
+            reason: String::new(),
+            retained_size,
+            path: paths[&object_id].clone(),
+            library_leak: None,
+        };
+        let leaks = vec![
+            leak(first, "a", 100),
+            leak(other, "a", 150),
+            leak(second, "a", 200),
+            // reported twice, counted once
+            leak(first, "b", 100),
+        ];
+        let groups = group_leaks(&graph, leaks);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups[0].signature,
+            "System class > static com.example.Cache.sModels > java.lang.Object[][] > com.example.Model"
+        );
+        assert_eq!(groups[0].count(), 2);
+        assert_eq!(groups[0].retained_size, 300);
+        let ids: Vec<u64> = groups[0].leaks.iter().map(|leak| leak.object_id).collect();
+        assert_eq!(ids, vec![second, first]);
+        assert_eq!(groups[0].leaks[1].detector, "a");
+        assert_eq!(groups[0].signature_hash(), "da0022def093792d");
+
+        assert_eq!(
+            groups[1].signature,
+            "System class > static com.example.Cache.sOther > com.example.Model"
+        );
+        assert_eq!(groups[1].count(), 1);
+        assert_eq!(groups[1].retained_size, 150);
+        assert_ne!(groups[1].signature_hash(), groups[0].signature_hash());
+    }
+}
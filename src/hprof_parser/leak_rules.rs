@@ -0,0 +1,241 @@
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::leak::{LeakCandidate, LeakDetector};
+use crate::hprof_parser::path::simple_class_name;
+use crate::hprof_parser::snapshot::JavaValue;
+use crate::hprof_parser::strings::string_value;
+use crate::Result;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+/// Value a field is compared to, the TOML type decides how: booleans and
+/// numbers against primitive fields, strings against `java.lang.String` content.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum FieldValueMatch {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl FieldValueMatch {
+    pub fn matches(&self, graph: &HeapGraph, value: JavaValue) -> bool {
+        match (self, value) {
+            (FieldValueMatch::Boolean(expected), JavaValue::Boolean(value)) => *expected == value,
+            (FieldValueMatch::Integer(expected), value) => integer(value) == Some(*expected),
+            (FieldValueMatch::Float(expected), JavaValue::Float(value)) => {
+                *expected == value as f64
+            }
+            (FieldValueMatch::Float(expected), JavaValue::Double(value)) => *expected == value,
+            (FieldValueMatch::String(expected), JavaValue::Object(id)) if id != 0 => {
+                string_value(graph, id).is_some_and(|value| value == expected.as_str())
+            }
+            _ => false,
+        }
+    }
+}
+
+fn integer(value: JavaValue) -> Option<i64> {
+    match value {
+        JavaValue::Byte(value) => Some(value as i64),
+        JavaValue::Short(value) => Some(value as i64),
+        JavaValue::Char(value) => Some(value as i64),
+        JavaValue::Int(value) => Some(value as i64),
+        JavaValue::Long(value) => Some(value),
+        _ => None,
+    }
+}
+
+impl Display for FieldValueMatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValueMatch::Boolean(value) => write!(f, "{}", value),
+            FieldValueMatch::Integer(value) => write!(f, "{}", value),
+            FieldValueMatch::Float(value) => write!(f, "{}", value),
+            FieldValueMatch::String(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+/// Instances of `class`, or of a subclass, are leaked once `field` holds
+/// `equals`, e.g. a presenter whose `destroyed` flag is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldLeakDetector {
+    /// name of the detector in reports, the class name when missing
+    pub name: Option<String>,
+    pub class: String,
+    pub field: String,
+    pub equals: FieldValueMatch,
+    /// `Presenter#destroyed is true` when missing
+    pub reason: Option<String>,
+}
+
+impl LeakDetector for FieldLeakDetector {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.class)
+    }
+
+    fn detect(&self, graph: &HeapGraph) -> Vec<LeakCandidate> {
+        graph
+            .instances_of(&self.class)
+            .into_iter()
+            .filter(|id| {
+                graph
+                    .field(*id, &self.field)
+                    .is_some_and(|value| self.equals.matches(graph, value))
+            })
+            .map(|id| LeakCandidate {
+                object_id: id,
+                reason: self.reason.clone().unwrap_or_else(|| {
+                    format!(
+                        "{}#{} is {}",
+                        simple_class_name(&self.class),
+                        self.field,
+                        self.equals
+                    )
+                }),
+            })
+            .collect()
+    }
+}
+
+/// A field leak traces must not go through, see [`crate::hprof_parser::PathFinder::exclude_field`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathExclusion {
+    /// class declaring the field
+    pub class: String,
+    pub field: String,
+}
+
+/// App specific leak detectors and path exclusions, written in TOML:
+///
+/// ```toml
+/// [[detector]]
+/// class = "com.foo.BasePresenter"
+/// field = "destroyed"
+/// equals = true
+///
+/// [[exclude]]
+/// class = "com.foo.EventBus"
+/// field = "subscribers"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeakRules {
+    #[serde(default, rename = "detector")]
+    pub detectors: Vec<FieldLeakDetector>,
+    #[serde(default, rename = "exclude")]
+    pub exclusions: Vec<PathExclusion>,
+}
+
+impl LeakRules {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::JavaType;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    const RULES: &str = r#"
+[[detector]]
+class = "com.foo.BasePresenter"
+field = "destroyed"
+equals = true
+
+[[detector]]
+name = "closed-db"
+class = "com.foo.Database"
+field = "state"
+equals = 3
+reason = "database is closed"
+
+[[exclude]]
+class = "com.foo.EventBus"
+field = "subscribers"
+"#;
+
+    #[test]
+    fn parse_rules() {
+        let rules = LeakRules::parse(RULES).unwrap();
+        let [presenter, database] = rules.detectors.as_slice() else {
+            panic!("expected 2 detectors, got {:?}", rules.detectors);
+        };
+        assert_eq!(presenter.name(), "com.foo.BasePresenter");
+        assert_eq!(presenter.equals, FieldValueMatch::Boolean(true));
+        assert_eq!(presenter.reason, None);
+        assert_eq!(database.name(), "closed-db");
+        assert_eq!(database.equals, FieldValueMatch::Integer(3));
+        assert_eq!(
+            rules.exclusions,
+            vec![PathExclusion {
+                class: "com.foo.EventBus".to_string(),
+                field: "subscribers".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn value_types() {
+        let equals = |text: &str| {
+            let rules = format!(
+                "[[detector]]\nclass = \"A\"\nfield = \"b\"\nequals = {}\n",
+                text
+            );
+            LeakRules::parse(&rules).unwrap().detectors[0]
+                .equals
+                .clone()
+        };
+        assert_eq!(equals("false"), FieldValueMatch::Boolean(false));
+        assert_eq!(equals("-1"), FieldValueMatch::Integer(-1));
+        assert_eq!(equals("0.5"), FieldValueMatch::Float(0.5));
+        assert_eq!(
+            equals("\"closed\""),
+            FieldValueMatch::String("closed".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(LeakRules::parse("[[detector]]\nclass = \"A\"\nfield = \"b\"\n").is_err());
+        assert!(
+            LeakRules::parse("[[exclude]]\nclass = \"A\"\nfield = \"b\"\nstatic = true\n").is_err()
+        );
+        let rules = LeakRules::parse("").unwrap();
+        assert!(rules.detectors.is_empty() && rules.exclusions.is_empty());
+    }
+
+    #[test]
+    fn detect_by_field_value() {
+        let mut dump = DumpBuilder::new();
+        let base = dump.class(
+            "com.foo.BasePresenter",
+            0,
+            &[("destroyed", JavaType::Boolean)],
+        );
+        let presenter = dump.class("com.foo.LoginPresenter", base, &[("state", JavaType::Int)]);
+        let destroyed = dump.instance(presenter, &[("destroyed", JavaValue::Boolean(true))]);
+        dump.instance(presenter, &[("state", JavaValue::Int(3))]);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let rules = LeakRules::parse(RULES).unwrap();
+        let candidates = rules.detectors[0].detect(&graph);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].object_id, destroyed);
+        assert_eq!(candidates[0].reason, "BasePresenter#destroyed is true");
+        // no Database in the dump
+        assert!(rules.detectors[1].detect(&graph).is_empty());
+    }
+}
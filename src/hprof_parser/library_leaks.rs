@@ -0,0 +1,334 @@
+use crate::hprof_parser::graph::{EdgeKind, HeapGraph};
+use crate::hprof_parser::histogram::matches_pattern;
+use crate::hprof_parser::path::ReferencePath;
+use crate::hprof_parser::snapshot::JavaValue;
+use crate::hprof_parser::strings::string_value;
+use crate::Result;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Rules shipped with the crate, see [`LibraryLeakRules::android`].
+const ANDROID_RULES: &str = include_str!("library_leaks.toml");
+
+/// Device a dump was taken on, read from the statics of `android.os.Build`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Device {
+    /// `Build.VERSION.SDK_INT`
+    pub api_level: Option<i32>,
+    /// `Build.MANUFACTURER`
+    pub manufacturer: Option<String>,
+}
+
+impl Device {
+    /// Fields that cannot be found are left to `None`, e.g. for hotspot dumps.
+    pub fn from_graph(graph: &HeapGraph) -> Self {
+        let api_level = match static_field(graph, "android.os.Build$VERSION", "SDK_INT") {
+            Some(JavaValue::Int(api_level)) => Some(api_level),
+            _ => None,
+        };
+        let manufacturer = match static_field(graph, "android.os.Build", "MANUFACTURER") {
+            Some(JavaValue::Object(id)) => string_value(graph, id).map(String::from),
+            _ => None,
+        };
+        Self {
+            api_level,
+            manufacturer,
+        }
+    }
+}
+
+fn static_field(graph: &HeapGraph, class_name: &str, field_name: &str) -> Option<JavaValue> {
+    graph.class_ids(class_name).iter().find_map(|class_id| {
+        graph
+            .static_fields(*class_id)
+            .into_iter()
+            .find(|field| field.name == field_name)
+            .map(|field| field.value)
+    })
+}
+
+/// A reference known to leak because of the platform or a library, matched
+/// against the fields of a leak trace.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LibraryLeakRule {
+    /// class holding the field or one of its superclasses, `*` and `?` match
+    /// as in [`matches_pattern`]
+    pub class: String,
+    pub field: String,
+    #[serde(default, rename = "static")]
+    pub is_static: bool,
+    pub description: String,
+    /// inclusive, the rule applies to every level when missing
+    pub min_api: Option<i32>,
+    pub max_api: Option<i32>,
+    /// compared to `Build.MANUFACTURER` ignoring case, empty for every manufacturer
+    #[serde(default)]
+    pub manufacturers: Vec<String>,
+}
+
+impl LibraryLeakRule {
+    /// Whether the device is affected, what the dump does not tell is assumed
+    /// to match.
+    pub fn applies_to(&self, device: &Device) -> bool {
+        if let Some(api_level) = device.api_level {
+            if self.min_api.is_some_and(|min| api_level < min)
+                || self.max_api.is_some_and(|max| api_level > max)
+            {
+                return false;
+            }
+        }
+        match &device.manufacturer {
+            Some(manufacturer) if !self.manufacturers.is_empty() => self
+                .manufacturers
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(manufacturer)),
+            _ => true,
+        }
+    }
+
+    /// Whether the path goes through the field.
+    pub fn matches(&self, graph: &HeapGraph, path: &ReferencePath) -> bool {
+        path.steps.iter().any(|step| {
+            let (class_id, name) = match step.edge {
+                // the class of the instance, which may only inherit the field
+                Some(EdgeKind::InstanceField { name, .. }) if !self.is_static => {
+                    match graph.class_of(step.object_id) {
+                        Some(class_id) => (class_id, name),
+                        None => return false,
+                    }
+                }
+                Some(EdgeKind::StaticField { class_id, name }) if self.is_static => {
+                    (class_id, name)
+                }
+                _ => return false,
+            };
+            name == self.field
+                && graph
+                    .class_hierarchy(class_id)
+                    .filter_map(|class_id| graph.class_name(class_id))
+                    .any(|class_name| self.matches_class(class_name))
+        })
+    }
+
+    /// A name without wildcards must match exactly, not as a substring.
+    fn matches_class(&self, class_name: &str) -> bool {
+        if self.class.contains(['*', '?']) {
+            matches_pattern(&self.class, class_name)
+        } else {
+            self.class == class_name
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    #[serde(default)]
+    leak: Vec<LibraryLeakRule>,
+}
+
+/// A set of [`LibraryLeakRule`]s, written in TOML as `[[leak]]` tables:
+///
+/// ```toml
+/// [[leak]]
+/// class = "android.view.inputmethod.InputMethodManager"
+/// field = "mNextServedView"
+/// max_api = 33
+/// manufacturers = ["samsung"]
+/// description = "..."
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LibraryLeakRules {
+    rules: Vec<LibraryLeakRule>,
+}
+
+impl LibraryLeakRules {
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: RuleFile = toml::from_str(text)?;
+        Ok(Self { rules: file.leak })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Known leaks of the Android framework and of manufacturer builds of it.
+    pub fn android() -> Self {
+        Self::parse(ANDROID_RULES).expect("bundled library leak rules are valid")
+    }
+
+    pub fn rules(&self) -> &[LibraryLeakRule] {
+        &self.rules
+    }
+
+    pub fn extend(mut self, other: LibraryLeakRules) -> Self {
+        self.rules.extend(other.rules);
+        self
+    }
+
+    /// Only the rules affecting `device`.
+    pub fn for_device(&self, device: &Device) -> Self {
+        Self {
+            rules: self
+                .rules
+                .iter()
+                .filter(|rule| rule.applies_to(device))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// First rule matching the path, `None` for a leak of the app itself.
+    pub fn find_match(&self, graph: &HeapGraph, path: &ReferencePath) -> Option<&LibraryLeakRule> {
+        self.rules.iter().find(|rule| rule.matches(graph, path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::path::PathFinder;
+    use crate::hprof_parser::snapshot::{JavaType, JavaValue};
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    const RULES: &str = r#"
+[[leak]]
+class = "android.view.inputmethod.InputMethodManager"
+field = "mNextServedView"
+min_api = 15
+max_api = 33
+description = "next served view"
+
+[[leak]]
+class = "android.media.AudioManager"
+field = "mContext_static"
+static = true
+manufacturers = ["samsung"]
+description = "static context"
+"#;
+
+    fn device(api_level: Option<i32>, manufacturer: Option<&str>) -> Device {
+        Device {
+            api_level,
+            manufacturer: manufacturer.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rules = LibraryLeakRules::parse(RULES).unwrap();
+        let [next_served_view, audio_manager] = rules.rules() else {
+            panic!("expected 2 rules, got {:?}", rules.rules());
+        };
+        assert_eq!(next_served_view.field, "mNextServedView");
+        assert!(!next_served_view.is_static);
+        assert_eq!(next_served_view.min_api, Some(15));
+        assert!(next_served_view.manufacturers.is_empty());
+        assert!(audio_manager.is_static);
+        assert_eq!(audio_manager.max_api, None);
+        assert_eq!(audio_manager.manufacturers, vec!["samsung"]);
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(LibraryLeakRules::parse("[[leak]]\nclass = \"a.B\"\n").is_err());
+        let unknown = "[[leak]]\nclass = \"a.B\"\nfield = \"c\"\ndescription = \"\"\nmax = 3\n";
+        assert!(LibraryLeakRules::parse(unknown).is_err());
+        assert!(LibraryLeakRules::parse("").unwrap().rules().is_empty());
+    }
+
+    #[test]
+    fn bundled_rules() {
+        let rules = LibraryLeakRules::android();
+        assert!(!rules.rules().is_empty());
+        let clipboard = rules
+            .rules()
+            .iter()
+            .find(|rule| rule.class == "android.sec.clipboard.ClipboardUIManager")
+            .unwrap();
+        assert!(!clipboard.is_static);
+    }
+
+    #[test]
+    fn devices() {
+        let rules = LibraryLeakRules::parse(RULES).unwrap();
+        let [next_served_view, audio_manager] = rules.rules() else {
+            unreachable!();
+        };
+        assert!(next_served_view.applies_to(&device(Some(28), Some("Google"))));
+        assert!(!next_served_view.applies_to(&device(Some(34), None)));
+        assert!(!next_served_view.applies_to(&device(Some(14), None)));
+        // unknown levels and manufacturers match
+        assert!(next_served_view.applies_to(&device(None, None)));
+        assert!(audio_manager.applies_to(&device(None, None)));
+        assert!(audio_manager.applies_to(&device(Some(19), Some("Samsung"))));
+        assert!(!audio_manager.applies_to(&device(Some(19), Some("Google"))));
+        let google = rules.for_device(&device(Some(19), Some("Google")));
+        assert_eq!(google.rules().len(), 1);
+    }
+
+    #[test]
+    fn class_patterns() {
+        let rule = |class: &str| LibraryLeakRule {
+            class: class.to_string(),
+            field: "mContext".to_string(),
+            is_static: false,
+            description: String::new(),
+            min_api: None,
+            max_api: None,
+            manufacturers: Vec::new(),
+        };
+        assert!(rule("android.app.Activity").matches_class("android.app.Activity"));
+        // no substring match without wildcards
+        assert!(!rule("android.app.Activity").matches_class("android.app.ActivityThread"));
+        assert!(rule("com.samsung.*").matches_class("com.samsung.Clipboard$1"));
+        assert!(rule("*Manager?").matches_class("android.app.ActivityManagerN"));
+        assert!(!rule("*Manager").matches_class("android.app.ActivityManagerNative"));
+    }
+
+    #[test]
+    fn matches_path() {
+        let mut dump = DumpBuilder::new();
+        let view_class = dump.class("android.view.View", 0, &[]);
+        let view = dump.instance(view_class, &[]);
+        let manager_class = dump.class_with_statics(
+            "android.view.inputmethod.InputMethodManager",
+            0,
+            &[("mNextServedView", JavaType::Object)],
+            &[("sInstance", JavaValue::Object(0))],
+        );
+        let subclass = dump.class("com.vendor.VendorInputMethodManager", manager_class, &[]);
+        let manager = dump.instance(subclass, &[("mNextServedView", JavaValue::Object(view))]);
+        let holder = dump.class_with_statics(
+            "com.example.Holder",
+            0,
+            &[],
+            &[("sManager", JavaValue::Object(manager))],
+        );
+        dump.sticky_class(holder);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let path = PathFinder::new(&graph).shortest_path(view).unwrap();
+        assert_eq!(path.steps.len(), 3);
+
+        let rule = |class: &str, field: &str, is_static: bool| LibraryLeakRule {
+            class: class.to_string(),
+            field: field.to_string(),
+            is_static,
+            description: String::new(),
+            min_api: None,
+            max_api: None,
+            manufacturers: Vec::new(),
+        };
+        let manager_name = "android.view.inputmethod.InputMethodManager";
+        // the field is inherited by the class of the instance
+        assert!(rule(manager_name, "mNextServedView", false).matches(&graph, &path));
+        assert!(rule("*InputMethodManager", "mNextServedView", false).matches(&graph, &path));
+        assert!(!rule(manager_name, "mNextServedView", true).matches(&graph, &path));
+        assert!(!rule(manager_name, "mCurRootView", false).matches(&graph, &path));
+        assert!(rule("com.example.Holder", "sManager", true).matches(&graph, &path));
+        assert!(!rule("com.example.Holder", "sManager", false).matches(&graph, &path));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::hprof_parser::Error;
+use crate::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A method as written in the source, for a frame of obfuscated code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OriginalMethod<'m> {
+    pub name: &'m str,
+    /// the line of the frame translated to the source, unchanged when the
+    /// mapping has no line information
+    pub line_number: i32,
+}
+
+#[derive(Debug, Clone)]
+struct MethodMapping {
+    original_name: String,
+    /// lines of the minified code, `None` when the method is not split by lines
+    obfuscated_lines: Option<(i32, i32)>,
+    original_lines: Option<(i32, i32)>,
+}
+
+impl MethodMapping {
+    fn covers(&self, line_number: i32) -> bool {
+        match self.obfuscated_lines {
+            Some((start, end)) => (start..=end).contains(&line_number),
+            None => true,
+        }
+    }
+
+    fn original_line(&self, line_number: i32) -> i32 {
+        match (self.obfuscated_lines, self.original_lines) {
+            _ if line_number <= 0 => line_number,
+            (Some((start, end)), Some((original_start, original_end)))
+                if end - start == original_end - original_start =>
+            {
+                original_start + line_number - start
+            }
+            (_, Some((original_start, _))) => original_start,
+            (_, None) => line_number,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ClassMapping {
+    original_name: String,
+    source_file: Option<String>,
+    /// by obfuscated name
+    fields: HashMap<String, String>,
+    /// by obfuscated name, in the order of the mapping file
+    methods: HashMap<String, Vec<MethodMapping>>,
+}
+
+/// Names of a ProGuard or R8 `mapping.txt`, looked up by obfuscated class name.
+#[derive(Debug, Clone, Default)]
+pub struct ProguardMapping {
+    classes: HashMap<String, ClassMapping>,
+}
+
+impl ProguardMapping {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Class lines `com.foo.Bar -> a.b:` followed by their indented members:
+    /// `int count -> a` for fields and `12:15:void run(int):40:43 -> b` for
+    /// methods, line ranges being optional.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut classes = HashMap::new();
+        let mut current: Option<(String, ClassMapping)> = None;
+        for (index, line) in text.lines().enumerate() {
+            let invalid = || Error::InvalidMapping {
+                line: index + 1,
+                content: line.to_string(),
+            };
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(comment) = trimmed.strip_prefix('#') {
+                // R8 metadata: # {"id":"sourceFile","fileName":"Bar.kt"}
+                if let Some((_, class)) = current.as_mut() {
+                    if comment.contains("\"sourceFile\"") {
+                        class.source_file = json_string(comment, "fileName").map(str::to_string);
+                    }
+                }
+                continue;
+            }
+
+            if !line.starts_with(char::is_whitespace) {
+                let (original, obfuscated) = trimmed
+                    .strip_suffix(':')
+                    .and_then(|line| line.split_once(" -> "))
+                    .ok_or_else(invalid)?;
+                if let Some((obfuscated, class)) = current.take() {
+                    classes.insert(obfuscated, class);
+                }
+                let class = ClassMapping {
+                    original_name: original.trim().to_string(),
+                    ..ClassMapping::default()
+                };
+                current = Some((obfuscated.trim().to_string(), class));
+                continue;
+            }
+
+            let (_, class) = current.as_mut().ok_or_else(invalid)?;
+            let (member, obfuscated) = trimmed.split_once(" -> ").ok_or_else(invalid)?;
+            let obfuscated = obfuscated.trim().to_string();
+            if member.contains('(') {
+                let method = parse_method(member).ok_or_else(invalid)?;
+                class.methods.entry(obfuscated).or_default().push(method);
+            } else {
+                let (_, name) = member.trim().rsplit_once(' ').ok_or_else(invalid)?;
+                class.fields.insert(obfuscated, name.to_string());
+            }
+        }
+        if let Some((obfuscated, class)) = current {
+            classes.insert(obfuscated, class);
+        }
+        Ok(Self { classes })
+    }
+
+    /// Original name of a class, `None` if the mapping does not list it.
+    pub fn class_name(&self, obfuscated: &str) -> Option<&str> {
+        self.classes
+            .get(obfuscated)
+            .map(|class| class.original_name.as_str())
+    }
+
+    /// Like [`Self::class_name`], also for arrays of mapped classes written
+    /// `a.b[]` or `[La.b;`.
+    pub fn type_name(&self, obfuscated: &str) -> Option<String> {
+        if let Some(element) = obfuscated.strip_suffix("[]") {
+            return Some(format!("{}[]", self.type_name(element)?));
+        }
+        if let Some(element) = obfuscated.strip_prefix('[') {
+            return match element.strip_prefix('L').and_then(|e| e.strip_suffix(';')) {
+                Some(class) => Some(format!("[L{};", self.class_name(class)?)),
+                None => Some(format!("[{}", self.type_name(element)?)),
+            };
+        }
+        self.class_name(obfuscated).map(str::to_string)
+    }
+
+    /// Original name of a field of `class`, the class declaring it: each class
+    /// of a hierarchy has its own field names.
+    pub fn field_name(&self, class: &str, field: &str) -> Option<&str> {
+        self.classes
+            .get(class)?
+            .fields
+            .get(field)
+            .map(String::as_str)
+    }
+
+    /// Method of `class` running at `line_number` of the minified code. When
+    /// R8 inlined methods at that line the outermost one is returned, the one
+    /// actually belonging to `class`.
+    pub fn method(
+        &self,
+        class: &str,
+        method: &str,
+        line_number: i32,
+    ) -> Option<OriginalMethod<'_>> {
+        let candidates = self.classes.get(class)?.methods.get(method)?;
+        let mapping = candidates
+            .iter()
+            .rev()
+            .find(|mapping| mapping.covers(line_number))
+            .or_else(|| candidates.last())?;
+        Some(OriginalMethod {
+            name: &mapping.original_name,
+            line_number: mapping.original_line(line_number),
+        })
+    }
+
+    /// Source file recorded by R8 for a class.
+    pub fn source_file(&self, class: &str) -> Option<&str> {
+        self.classes.get(class)?.source_file.as_deref()
+    }
+}
+
+/// `12:15:void run(int):40:43`, `void run()`, or `1:1:void com.foo.Util.log():7:7`
+/// for a method inlined from another class.
+fn parse_method(member: &str) -> Option<MethodMapping> {
+    let (ranges, rest) = split_line_range(member.trim());
+    let open = rest.find('(')?;
+    let close = open + rest[open..].find(')')?;
+    let (_, name) = rest[..open].trim().rsplit_once(' ')?;
+    let name = name.rsplit('.').next()?;
+    let original_lines = match rest[close + 1..].strip_prefix(':') {
+        Some(lines) => Some(match lines.split_once(':') {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None => {
+                let line = lines.parse().ok()?;
+                (line, line)
+            }
+        }),
+        None => None,
+    };
+    Some(MethodMapping {
+        original_name: name.to_string(),
+        obfuscated_lines: ranges,
+        original_lines,
+    })
+}
+
+fn split_line_range(member: &str) -> (Option<(i32, i32)>, &str) {
+    let mut parts = member.splitn(3, ':');
+    if let (Some(start), Some(end), Some(rest)) = (parts.next(), parts.next(), parts.next()) {
+        if let (Ok(start), Ok(end)) = (start.parse(), end.parse()) {
+            return (Some((start, end)), rest);
+        }
+    }
+    (None, member)
+}
+
+/// Value of a string `key` in the flat JSON of an R8 comment.
+fn json_string<'t>(json: &'t str, key: &str) -> Option<&'t str> {
+    let (_, rest) = json.split_once(&format!("\"{}\"", key))?;
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    rest.split_once('"').map(|(value, _)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &str = "\
+# compiler: R8
+com.foo.MainActivity -> a.b:
+# {\"id\":\"sourceFile\",\"fileName\":\"MainActivity.kt\"}
+    android.view.View content -> a
+    int count -> b
+    1:1:void com.foo.Util.log(java.lang.String):7:7 -> c
+    1:1:void onCreate(android.os.Bundle):20 -> c
+    2:4:void onCreate(android.os.Bundle):21:23 -> c
+    5:5:void onCreate(android.os.Bundle):30:35 -> c
+    void onResume() -> d
+com.foo.Model -> a.c:
+    java.lang.String name -> a
+";
+
+    #[test]
+    fn classes_and_fields() {
+        let mapping = ProguardMapping::parse(MAPPING).unwrap();
+        assert_eq!(mapping.class_name("a.b"), Some("com.foo.MainActivity"));
+        assert_eq!(mapping.class_name("a.c"), Some("com.foo.Model"));
+        assert_eq!(mapping.class_name("a.d"), None);
+        assert_eq!(mapping.field_name("a.b", "a"), Some("content"));
+        assert_eq!(mapping.field_name("a.b", "b"), Some("count"));
+        assert_eq!(mapping.field_name("a.c", "a"), Some("name"));
+        assert_eq!(mapping.field_name("a.c", "b"), None);
+        assert_eq!(mapping.source_file("a.b"), Some("MainActivity.kt"));
+        assert_eq!(mapping.source_file("a.c"), None);
+    }
+
+    #[test]
+    fn array_types() {
+        let mapping = ProguardMapping::parse(MAPPING).unwrap();
+        assert_eq!(
+            mapping.type_name("a.c[]"),
+            Some("com.foo.Model[]".to_string())
+        );
+        assert_eq!(
+            mapping.type_name("[[La.c;"),
+            Some("[[Lcom.foo.Model;".to_string())
+        );
+        assert_eq!(mapping.type_name("int[]"), None);
+    }
+
+    #[test]
+    fn methods_by_line() {
+        let mapping = ProguardMapping::parse(MAPPING).unwrap();
+        let method = |line| mapping.method("a.b", "c", line).unwrap();
+        // Util.log was inlined at line 1, the frame belongs to onCreate
+        assert_eq!(
+            method(1),
+            OriginalMethod {
+                name: "onCreate",
+                line_number: 20
+            }
+        );
+        assert_eq!(method(3).line_number, 22);
+        // ranges of different lengths map to the start of the original one
+        assert_eq!(method(5).line_number, 30);
+        // lines outside every range fall back to the last mapping
+        assert_eq!(method(9).name, "onCreate");
+        assert_eq!(
+            mapping.method("a.b", "d", 12),
+            Some(OriginalMethod {
+                name: "onResume",
+                line_number: 12
+            })
+        );
+        assert_eq!(mapping.method("a.b", "e", 1), None);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let error = ProguardMapping::parse("com.foo.Model -> a.c:\n    broken\n").unwrap_err();
+        assert!(matches!(error, Error::InvalidMapping { line: 2, .. }));
+        assert!(ProguardMapping::parse("    int count -> a\n").is_err());
+        assert!(ProguardMapping::parse("com.foo.Model a.c\n").is_err());
+    }
+}
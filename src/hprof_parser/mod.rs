@@ -1,33 +1,52 @@
 use crate::hprof_parser::snapshot::Snapshot;
 use memmap::Mmap;
-use std::collections::HashMap;
 use std::fs::File;
 use std::path::Path;
 use std::result::Result as StdResult;
 
-mod constant;
+pub mod allocations;
+pub mod bitmap;
+pub mod collections;
+pub mod constant;
+pub mod diff;
 // mod parser;
-mod snapshot;
+pub mod dominator;
+pub mod finalizer;
+pub mod graph;
+pub mod histogram;
+pub mod leak;
+pub mod leak_rules;
+pub mod library_leaks;
+pub mod mapping;
+pub mod native;
+pub mod path;
+pub mod reachability;
+pub mod size_model;
+pub mod snapshot;
+pub mod statics;
+pub mod strings;
+pub mod synthetic;
+pub mod threads;
 
 mod errors;
+#[cfg(test)]
+mod test_dump;
 
+pub use dominator::DominatorTree;
 pub use errors::Error;
+pub use graph::HeapGraph;
+pub use leak::LeakFinder;
+pub use mapping::ProguardMapping;
+pub use path::PathFinder;
+pub use size_model::SizeModel;
 pub type Result<T> = StdResult<T, Error>;
 
 #[derive(Debug)]
 pub struct HprofParser<'a> {
     snapshot: Snapshot<'a>,
-    result: HprofResult<'a>,
 }
 
-#[derive(Default, Debug)]
-pub struct HprofResult<'a> {
-    string_map: HashMap<u64, &'a str>,
-    class_map_by_id: HashMap<u64, &'a str>,
-    class_map_by_serial: HashMap<u32, &'a str>,
-}
-
-impl<'hp: 'hr, 'hr> HprofParser<'hp> {
+impl<'hp> HprofParser<'hp> {
     pub fn parse<P: AsRef<Path>>(path: P) -> Result<()> {
         let file = File::open(path)?;
         Self::parse_file(&file)
@@ -35,13 +54,10 @@ impl<'hp: 'hr, 'hr> HprofParser<'hp> {
 
     pub fn parse_file(file: &File) -> Result<()> {
         let file_bytes = file.metadata().unwrap().len() as usize;
-        let mapped_file = unsafe { Mmap::map(&file) }?;
+        let mapped_file = unsafe { Mmap::map(file) }?;
         let snapshot = Snapshot::new(&mapped_file[0..file_bytes])?;
         println!("header: {:?}", snapshot.header());
-        let parser = HprofParser {
-            snapshot,
-            result: Default::default(),
-        };
+        let parser = HprofParser { snapshot };
         let _ = parser.parse_record();
         println!("{:?}", parser);
         Ok(())
@@ -0,0 +1,297 @@
+use crate::hprof_parser::dominator::DominatorTree;
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::snapshot::JavaValue;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Low bit of `NativeAllocationRegistry.size`, set when the native memory was
+/// allocated with malloc rather than being an estimate.
+const IS_MALLOCED: u64 = 0x1;
+
+/// Native memory registered for a Java object through
+/// `libcore.util.NativeAllocationRegistry`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NativeAllocation {
+    /// object the native memory is freed with
+    pub owner_id: u64,
+    /// the `sun.misc.Cleaner` holding the registration
+    pub cleaner_id: u64,
+    pub size: u64,
+}
+
+/// Every registration, found by following `Cleaner.thunk`, a
+/// `NativeAllocationRegistry$CleanerThunk`, to its registry and the Cleaner's
+/// `referent` to the owner.
+pub fn native_allocations(graph: &HeapGraph) -> Vec<NativeAllocation> {
+    let mut allocations: Vec<NativeAllocation> = graph
+        .instances_of("sun.misc.Cleaner")
+        .into_iter()
+        .filter_map(|cleaner| {
+            let owner_id = graph.referent(cleaner)?;
+            let Some(JavaValue::Object(thunk)) = graph.field(cleaner, "thunk") else {
+                return None;
+            };
+            let thunk_class = graph.class_of(thunk)?;
+            if !graph.is_subclass_of(
+                thunk_class,
+                "libcore.util.NativeAllocationRegistry$CleanerThunk",
+            ) {
+                return None;
+            }
+            let Some(JavaValue::Object(registry)) = graph.field(thunk, "this$0") else {
+                return None;
+            };
+            let Some(JavaValue::Long(size)) = graph.field(registry, "size") else {
+                return None;
+            };
+            Some(NativeAllocation {
+                owner_id,
+                cleaner_id: cleaner,
+                size: size as u64 & !IS_MALLOCED,
+            })
+        })
+        .collect();
+    allocations.sort_by_key(|allocation| allocation.cleaner_id);
+    allocations
+}
+
+/// An object owning native memory, with its Java retained size for comparison.
+#[derive(Debug, Clone)]
+pub struct NativeOwner {
+    pub object_id: u64,
+    pub class_name: String,
+    /// sum of the registrations of this object
+    pub native_size: u64,
+    pub retained_size: u64,
+}
+
+/// Owners of one class.
+#[derive(Debug, Clone)]
+pub struct NativeClassStats {
+    pub class_name: String,
+    pub owner_count: usize,
+    pub native_size: u64,
+    pub retained_size: u64,
+}
+
+/// Registered native sizes, per object and per object dominating them.
+#[derive(Debug, Clone)]
+pub struct NativeMemory {
+    owners: Vec<NativeOwner>,
+    registered: HashMap<u64, u64>,
+    retained_native: HashMap<u64, u64>,
+}
+
+impl NativeMemory {
+    pub fn new(graph: &HeapGraph, dominators: &DominatorTree) -> Self {
+        let mut registered: HashMap<u64, u64> = HashMap::new();
+        for allocation in native_allocations(graph) {
+            *registered.entry(allocation.owner_id).or_default() += allocation.size;
+        }
+
+        // the native memory of an object is also retained by its dominators
+        let retained_native =
+            dominators.retained_sum(|id| registered.get(&id).copied().unwrap_or_default());
+
+        let mut owners: Vec<NativeOwner> = registered
+            .iter()
+            .map(|(object_id, native_size)| NativeOwner {
+                object_id: *object_id,
+                class_name: graph.type_name(*object_id).unwrap_or_default(),
+                native_size: *native_size,
+                retained_size: dominators.retained_size(*object_id),
+            })
+            .collect();
+        owners.sort_by(|a, b| {
+            b.native_size
+                .cmp(&a.native_size)
+                .then_with(|| a.object_id.cmp(&b.object_id))
+        });
+        Self {
+            owners,
+            registered,
+            retained_native,
+        }
+    }
+
+    /// Largest native size first.
+    pub fn owners(&self) -> &[NativeOwner] {
+        &self.owners
+    }
+
+    /// Native memory registered for `id` itself.
+    pub fn native_size(&self, id: u64) -> u64 {
+        self.registered.get(&id).copied().unwrap_or_default()
+    }
+
+    /// Native memory of every object `id` dominates, itself included.
+    pub fn retained_native_size(&self, id: u64) -> u64 {
+        self.retained_native.get(&id).copied().unwrap_or_default()
+    }
+
+    /// Largest native size first.
+    pub fn by_class(&self) -> Vec<NativeClassStats> {
+        let mut classes: HashMap<&str, NativeClassStats> = HashMap::new();
+        for owner in &self.owners {
+            let stats = classes
+                .entry(&owner.class_name)
+                .or_insert_with(|| NativeClassStats {
+                    class_name: owner.class_name.clone(),
+                    owner_count: 0,
+                    native_size: 0,
+                    retained_size: 0,
+                });
+            stats.owner_count += 1;
+            stats.native_size += owner.native_size;
+            stats.retained_size += owner.retained_size;
+        }
+        let mut classes: Vec<NativeClassStats> = classes.into_values().collect();
+        classes.sort_by(|a, b| {
+            b.native_size
+                .cmp(&a.native_size)
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        classes
+    }
+}
+
+impl Display for NativeMemory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>10} {:>12} {:>12}  class",
+            "count", "native", "retained"
+        )?;
+        for stats in self.by_class() {
+            writeln!(
+                f,
+                "{:>10} {:>12} {:>12}  {}",
+                stats.owner_count, stats.native_size, stats.retained_size, stats.class_name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::JavaType;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    #[test]
+    fn registered_native_memory() {
+        let mut dump = DumpBuilder::new();
+        let reference = dump.class(
+            "java.lang.ref.Reference",
+            0,
+            &[("referent", JavaType::Object)],
+        );
+        let phantom = dump.class("java.lang.ref.PhantomReference", reference, &[]);
+        let cleaner_class = dump.class("sun.misc.Cleaner", phantom, &[("thunk", JavaType::Object)]);
+        let registry_class = dump.class(
+            "libcore.util.NativeAllocationRegistry",
+            0,
+            &[("size", JavaType::Long)],
+        );
+        let thunk_class = dump.class(
+            "libcore.util.NativeAllocationRegistry$CleanerThunk",
+            0,
+            &[("this$0", JavaType::Object)],
+        );
+        let runnable_class = dump.class("com.example.Cleanup", 0, &[]);
+        let bitmap_class = dump.class("android.graphics.Bitmap", 0, &[]);
+        let gallery_class = dump.class(
+            "com.example.Gallery",
+            0,
+            &[("first", JavaType::Object), ("second", JavaType::Object)],
+        );
+
+        // the low bit only tells the memory was malloced
+        let malloced = dump.instance(registry_class, &[("size", JavaValue::Long(1001))]);
+        let estimated = dump.instance(registry_class, &[("size", JavaValue::Long(512))]);
+        let bitmaps: Vec<u64> = (0..3).map(|_| dump.instance(bitmap_class, &[])).collect();
+        let gallery = dump.instance(
+            gallery_class,
+            &[
+                ("first", JavaValue::Object(bitmaps[0])),
+                ("second", JavaValue::Object(bitmaps[1])),
+            ],
+        );
+        dump.root(gallery);
+        let mut cleaners = Vec::new();
+        for (owner, registry) in [
+            (bitmaps[0], malloced),
+            (bitmaps[0], estimated),
+            (bitmaps[1], estimated),
+            (bitmaps[2], estimated),
+        ] {
+            let thunk = dump.instance(thunk_class, &[("this$0", JavaValue::Object(registry))]);
+            let cleaner = dump.instance(
+                cleaner_class,
+                &[
+                    ("referent", JavaValue::Object(owner)),
+                    ("thunk", JavaValue::Object(thunk)),
+                ],
+            );
+            dump.root(cleaner);
+            cleaners.push(cleaner);
+        }
+        // not a registration
+        let runnable = dump.instance(runnable_class, &[]);
+        let other = dump.instance(
+            cleaner_class,
+            &[
+                ("referent", JavaValue::Object(bitmaps[1])),
+                ("thunk", JavaValue::Object(runnable)),
+            ],
+        );
+        dump.root(other);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let dominators = DominatorTree::new(&graph);
+
+        let allocations = native_allocations(&graph);
+        let found: Vec<(u64, u64, u64)> = allocations
+            .iter()
+            .map(|a| (a.owner_id, a.cleaner_id, a.size))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (bitmaps[0], cleaners[0], 1000),
+                (bitmaps[0], cleaners[1], 512),
+                (bitmaps[1], cleaners[2], 512),
+                (bitmaps[2], cleaners[3], 512),
+            ]
+        );
+
+        let native = NativeMemory::new(&graph, &dominators);
+        let owners: Vec<(u64, u64)> = native
+            .owners()
+            .iter()
+            .map(|owner| (owner.object_id, owner.native_size))
+            .collect();
+        assert_eq!(
+            owners,
+            vec![(bitmaps[0], 1512), (bitmaps[1], 512), (bitmaps[2], 512)]
+        );
+        assert_eq!(native.owners()[0].class_name, "android.graphics.Bitmap");
+        assert_eq!(
+            native.owners()[0].retained_size,
+            graph.shallow_size(bitmaps[0])
+        );
+        assert_eq!(native.native_size(bitmaps[0]), 1512);
+        assert_eq!(native.native_size(gallery), 0);
+        assert_eq!(native.retained_native_size(bitmaps[0]), 1512);
+        // the third bitmap is only reachable through its Cleaner
+        assert_eq!(native.retained_native_size(gallery), 2024);
+        assert_eq!(native.retained_native_size(bitmaps[2]), 0);
+
+        let classes = native.by_class();
+        assert_eq!(classes.len(), 1);
+        assert_eq!(classes[0].class_name, "android.graphics.Bitmap");
+        assert_eq!(classes[0].owner_count, 3);
+        assert_eq!(classes[0].native_size, 2536);
+    }
+}
@@ -0,0 +1,465 @@
+use crate::hprof_parser::graph::{Edge, EdgeKind, HeapGraph, ReferenceStrength};
+use crate::hprof_parser::snapshot::SubTag;
+use crate::hprof_parser::synthetic::{captured_field, synthetic_class};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+/// Finds the shortest reference chains from gc roots to objects with a
+/// breadth first walk of the heap graph.
+///
+/// Soft, weak, phantom and finalizer references are not followed by default,
+/// so the chains found are the ones that really keep an object alive.
+pub struct PathFinder<'g, 'a> {
+    graph: &'g HeapGraph<'a>,
+    excluded_strengths: HashSet<ReferenceStrength>,
+    excluded_fields: Vec<(String, String)>,
+}
+
+impl<'g, 'a> PathFinder<'g, 'a> {
+    pub fn new(graph: &'g HeapGraph<'a>) -> Self {
+        Self {
+            graph,
+            excluded_strengths: HashSet::from([
+                ReferenceStrength::Soft,
+                ReferenceStrength::Weak,
+                ReferenceStrength::Phantom,
+                ReferenceStrength::Finalizer,
+            ]),
+            excluded_fields: Vec::new(),
+        }
+    }
+
+    pub fn include_reference(mut self, strength: ReferenceStrength) -> Self {
+        self.excluded_strengths.remove(&strength);
+        self
+    }
+
+    pub fn exclude_reference(mut self, strength: ReferenceStrength) -> Self {
+        if strength != ReferenceStrength::Strong {
+            self.excluded_strengths.insert(strength);
+        }
+        self
+    }
+
+    /// Never walk through `class_name.field_name`, static or not.
+    /// `class_name` is the class declaring the field.
+    pub fn exclude_field(mut self, class_name: &str, field_name: &str) -> Self {
+        self.excluded_fields
+            .push((class_name.to_string(), field_name.to_string()));
+        self
+    }
+
+    pub fn shortest_path(&self, target: u64) -> Option<ReferencePath<'a>> {
+        self.shortest_paths(&[target]).remove(&target)
+    }
+
+    /// Shortest path of each target, computed in a single walk.
+    /// Targets that cannot be reached are missing from the result.
+    pub fn shortest_paths(&self, targets: &[u64]) -> HashMap<u64, ReferencePath<'a>> {
+        let mut remaining: HashSet<u64> = targets.iter().copied().collect();
+        let mut root_names: HashMap<u64, &'static str> = HashMap::new();
+        let mut parents: HashMap<u64, (u64, EdgeKind<'a>)> = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        for root in self.graph.gc_roots() {
+            if matches!(root, SubTag::RootFinalizing(_))
+                && self
+                    .excluded_strengths
+                    .contains(&ReferenceStrength::Finalizer)
+            {
+                continue;
+            }
+            let (Some(id), Some(name)) = (root.object_id(), root.root_name()) else {
+                continue;
+            };
+            if self.graph.object(id).is_some() && !root_names.contains_key(&id) {
+                root_names.insert(id, name);
+                queue.push_back(id);
+            }
+        }
+
+        let mut found = Vec::new();
+        while let Some(id) = queue.pop_front() {
+            if remaining.remove(&id) {
+                found.push(id);
+                if remaining.is_empty() {
+                    break;
+                }
+            }
+            for edge in self.graph.edges(id) {
+                if self.is_excluded(&edge)
+                    || root_names.contains_key(&edge.target)
+                    || parents.contains_key(&edge.target)
+                {
+                    continue;
+                }
+                parents.insert(edge.target, (id, edge.kind));
+                queue.push_back(edge.target);
+            }
+        }
+
+        found
+            .into_iter()
+            .map(|target| {
+                let mut steps = vec![PathStep {
+                    object_id: target,
+                    edge: None,
+                }];
+                let mut id = target;
+                while let Some((parent, kind)) = parents.get(&id) {
+                    steps.push(PathStep {
+                        object_id: *parent,
+                        edge: Some(*kind),
+                    });
+                    id = *parent;
+                }
+                steps.reverse();
+                let path = ReferencePath {
+                    root_name: root_names[&id],
+                    steps,
+                };
+                (target, path)
+            })
+            .collect()
+    }
+
+    fn is_excluded(&self, edge: &Edge) -> bool {
+        if self.excluded_strengths.contains(&edge.strength) {
+            return true;
+        }
+        match edge.kind {
+            EdgeKind::InstanceField { class_id, name }
+            | EdgeKind::StaticField { class_id, name } => {
+                let class_name = self.graph.class_name(class_id).unwrap_or_default();
+                self.excluded_fields
+                    .iter()
+                    .any(|(c, f)| c == class_name && f == name)
+            }
+            EdgeKind::ArrayElement(_) => false,
+        }
+    }
+}
+
+/// One object on a [`ReferencePath`] and the reference it follows to the next one.
+#[derive(Debug, Copy, Clone)]
+pub struct PathStep<'a> {
+    pub object_id: u64,
+    /// `None` for the last step, the object the path leads to
+    pub edge: Option<EdgeKind<'a>>,
+}
+
+/// Reference chain from a gc root to an object, the first step is the root.
+#[derive(Debug, Clone)]
+pub struct ReferencePath<'a> {
+    pub root_name: &'static str,
+    pub steps: Vec<PathStep<'a>>,
+}
+
+impl<'a> ReferencePath<'a> {
+    pub fn root_id(&self) -> u64 {
+        self.steps[0].object_id
+    }
+
+    pub fn target_id(&self) -> u64 {
+        self.steps[self.steps.len() - 1].object_id
+    }
+
+    /// Root kind followed by the class and field of every step, without array
+    /// indices nor object ids, so that it is stable across dumps:
+    /// `System class > com.foo.Bar.sCache > java.lang.Object[][] > com.foo.Model`.
+    pub fn signature(&self, graph: &HeapGraph) -> String {
+        let mut signature = self.root_name.to_string();
+        for step in &self.steps {
+            signature.push_str(" > ");
+            match step.edge {
+                Some(edge) => signature.push_str(&describe_referrer(graph, step.object_id, edge)),
+                None => signature.push_str(&graph.type_name(step.object_id).unwrap_or_default()),
+            }
+        }
+        signature
+    }
+
+    /// Formats the path like a LeakCanary leak trace.
+    pub fn display<'p>(&'p self, graph: &'p HeapGraph<'a>) -> PathDisplay<'p, 'a> {
+        PathDisplay { path: self, graph }
+    }
+}
+
+pub struct PathDisplay<'p, 'a> {
+    path: &'p ReferencePath<'a>,
+    graph: &'p HeapGraph<'a>,
+}
+
+impl<'p, 'a> Display for PathDisplay<'p, 'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "┬───")?;
+        writeln!(f, "│ GC Root: {}", self.path.root_name)?;
+        writeln!(f, "│")?;
+        for step in &self.path.steps {
+            let description = describe_object(self.graph, step.object_id);
+            match step.edge {
+                Some(edge) => {
+                    writeln!(f, "├─ {}", description)?;
+                    writeln!(
+                        f,
+                        "│    ↓ {}",
+                        describe_edge(self.graph, step.object_id, edge)
+                    )?;
+                }
+                None => writeln!(f, "╰→ {}", description)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `com.foo.Bar instance`, `com.foo.Bar class` or `int[] array`, followed by
+/// the enclosing class of anonymous classes and lambdas:
+/// `com.foo.Bar$1 instance (anonymous class in com.foo.Bar)`.
+pub fn describe_object(graph: &HeapGraph, id: u64) -> String {
+    let name = graph.type_name(id).unwrap_or_else(|| format!("{:#x}", id));
+    let suffix = match graph.object(id) {
+        Some(SubTag::ClassDump { .. }) => "class",
+        Some(SubTag::ObjectArrayDump { .. }) | Some(SubTag::PrimitiveArrayDump { .. }) => "array",
+        _ => "instance",
+    };
+    match synthetic_class(&name) {
+        Some(synthetic) => format!("{} {} ({})", name, suffix, synthetic),
+        None => format!("{} {}", name, suffix),
+    }
+}
+
+/// `Bar.mField`, `static Bar.sField` or `Object[][3]`. Fields generated for
+/// inner classes and lambdas say what they capture: `Bar$1.this$0 (captured
+/// outer instance)`.
+pub fn describe_edge(graph: &HeapGraph, from: u64, edge: EdgeKind) -> String {
+    let simple_name = |class_id: u64| -> String {
+        let name = graph.class_name(class_id).unwrap_or_default();
+        simple_class_name(name).to_string()
+    };
+    match edge {
+        EdgeKind::InstanceField { class_id, name } => {
+            let class_name = graph.class_name(class_id).unwrap_or_default();
+            match captured_field(class_name, name) {
+                Some(captured) => format!("{}.{} ({})", simple_name(class_id), name, captured),
+                None => format!("{}.{}", simple_name(class_id), name),
+            }
+        }
+        EdgeKind::StaticField { class_id, name } => {
+            format!("static {}.{}", simple_name(class_id), name)
+        }
+        EdgeKind::ArrayElement(index) => {
+            let array_name = graph.type_name(from).unwrap_or_default();
+            format!("{}[{}]", simple_class_name(&array_name), index)
+        }
+    }
+}
+
+/// Fully qualified form of [`describe_edge`] without array indices, so that
+/// every object held the same way gets the same description:
+/// `com.foo.Bar.mField`, `static com.foo.Bar.sField` or `java.lang.Object[][]`.
+pub fn describe_referrer(graph: &HeapGraph, from: u64, edge: EdgeKind) -> String {
+    match edge {
+        EdgeKind::InstanceField { class_id, name } => {
+            format!(
+                "{}.{}",
+                graph.class_name(class_id).unwrap_or_default(),
+                name
+            )
+        }
+        EdgeKind::StaticField { class_id, name } => {
+            format!(
+                "static {}.{}",
+                graph.class_name(class_id).unwrap_or_default(),
+                name
+            )
+        }
+        EdgeKind::ArrayElement(_) => format!("{}[]", graph.type_name(from).unwrap_or_default()),
+    }
+}
+
+/// `com.foo.Bar` -> `Bar`
+pub fn simple_class_name(name: &str) -> &str {
+    name.rsplit('.').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::{JavaType, JavaValue};
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    fn ids(path: &ReferencePath) -> Vec<u64> {
+        path.steps.iter().map(|step| step.object_id).collect()
+    }
+
+    #[test]
+    fn shortest_of_two_paths() {
+        let mut dump = DumpBuilder::new();
+        let fields = [("a", JavaType::Object), ("b", JavaType::Object)];
+        let node = dump.class("Node", 0, &fields);
+        let target = dump.instance(node, &[]);
+        let far = dump.instance(node, &[("a", JavaValue::Object(target))]);
+        let middle = dump.instance(node, &[("a", JavaValue::Object(far))]);
+        let near = dump.instance(node, &[("a", JavaValue::Object(target))]);
+        let root = dump.instance(
+            node,
+            &[
+                ("a", JavaValue::Object(middle)),
+                ("b", JavaValue::Object(near)),
+            ],
+        );
+        dump.root(root);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let path = PathFinder::new(&graph).shortest_path(target).unwrap();
+        assert_eq!(path.root_name, "Unknown");
+        assert_eq!(ids(&path), vec![root, near, target]);
+        assert_eq!(path.root_id(), root);
+        assert_eq!(path.target_id(), target);
+        assert_eq!(
+            path.steps[0].edge,
+            Some(EdgeKind::InstanceField {
+                class_id: node,
+                name: "b"
+            })
+        );
+        assert_eq!(path.steps[2].edge, None);
+    }
+
+    #[test]
+    fn references_are_skipped_by_default() {
+        let mut dump = DumpBuilder::new();
+        let reference = dump.class(
+            "java.lang.ref.Reference",
+            0,
+            &[("referent", JavaType::Object)],
+        );
+        let soft = dump.class("java.lang.ref.SoftReference", reference, &[]);
+        let weak = dump.class("java.lang.ref.WeakReference", reference, &[]);
+        let phantom = dump.class("java.lang.ref.PhantomReference", reference, &[]);
+        let node = dump.class("Node", 0, &[]);
+        let mut targets = Vec::new();
+        for class_id in [soft, weak, phantom] {
+            let target = dump.instance(node, &[]);
+            let holder = dump.instance(class_id, &[("referent", JavaValue::Object(target))]);
+            dump.root(holder);
+            targets.push(target);
+        }
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        assert!(PathFinder::new(&graph).shortest_paths(&targets).is_empty());
+
+        let strengths = [
+            ReferenceStrength::Soft,
+            ReferenceStrength::Weak,
+            ReferenceStrength::Phantom,
+        ];
+        for (target, strength) in targets.iter().zip(strengths) {
+            let paths = PathFinder::new(&graph)
+                .include_reference(strength)
+                .shortest_paths(&targets);
+            assert_eq!(paths.keys().collect::<Vec<_>>(), vec![target]);
+            assert_eq!(paths[target].steps.len(), 2);
+        }
+    }
+
+    #[test]
+    fn excluded_fields() {
+        let mut dump = DumpBuilder::new();
+        let node = dump.class("Node", 0, &[]);
+        let instance_target = dump.instance(node, &[]);
+        let static_target = dump.instance(node, &[]);
+        let holder = dump.class_with_statics(
+            "com.foo.Holder",
+            0,
+            &[("mCache", JavaType::Object)],
+            &[("sCache", JavaValue::Object(static_target))],
+        );
+        let instance = dump.instance(holder, &[("mCache", JavaValue::Object(instance_target))]);
+        dump.root(instance);
+        dump.sticky_class(holder);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let targets = [instance_target, static_target];
+
+        let paths = PathFinder::new(&graph).shortest_paths(&targets);
+        assert_eq!(
+            ids(&paths[&instance_target]),
+            vec![instance, instance_target]
+        );
+        assert_eq!(ids(&paths[&static_target]), vec![holder, static_target]);
+        assert_eq!(paths[&static_target].root_name, "System class");
+
+        let paths = PathFinder::new(&graph)
+            .exclude_field("com.foo.Holder", "mCache")
+            .shortest_paths(&targets);
+        assert!(!paths.contains_key(&instance_target));
+        assert!(paths.contains_key(&static_target));
+
+        let paths = PathFinder::new(&graph)
+            .exclude_field("com.foo.Holder", "sCache")
+            .shortest_paths(&targets);
+        assert!(paths.contains_key(&instance_target));
+        assert!(!paths.contains_key(&static_target));
+    }
+
+    #[test]
+    fn unreachable_target() {
+        let mut dump = DumpBuilder::new();
+        let node = dump.class("Node", 0, &[("a", JavaType::Object)]);
+        let reachable = dump.instance(node, &[]);
+        let unreachable = dump.instance(node, &[]);
+        let root = dump.instance(node, &[("a", JavaValue::Object(reachable))]);
+        dump.root(root);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let paths = PathFinder::new(&graph).shortest_paths(&[reachable, unreachable]);
+        assert!(paths.contains_key(&reachable));
+        assert!(!paths.contains_key(&unreachable));
+        assert!(PathFinder::new(&graph).shortest_path(unreachable).is_none());
+    }
+
+    #[test]
+    fn signature_ignores_indices_and_ids() {
+        let mut dump = DumpBuilder::new();
+        let model = dump.class("com.example.Model", 0, &[]);
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let first = dump.instance(model, &[]);
+        let second = dump.instance(model, &[]);
+        let other = dump.instance(model, &[]);
+        let array = dump.object_array(array_class, &[first, 0, second]);
+        let holder = dump.class_with_statics(
+            "com.example.Cache",
+            0,
+            &[],
+            &[
+                ("sModels", JavaValue::Object(array)),
+                ("sOther", JavaValue::Object(other)),
+            ],
+        );
+        dump.sticky_class(holder);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let paths = PathFinder::new(&graph).shortest_paths(&[first, second, other]);
+        assert_eq!(paths[&first].steps[1].edge, Some(EdgeKind::ArrayElement(0)));
+        assert_eq!(
+            paths[&second].steps[1].edge,
+            Some(EdgeKind::ArrayElement(2))
+        );
+        let signature = paths[&first].signature(&graph);
+        assert_eq!(
+            signature,
+            "System class > static com.example.Cache.sModels > java.lang.Object[][] > com.example.Model"
+        );
+        assert_eq!(paths[&second].signature(&graph), signature);
+        assert_eq!(
+            paths[&other].signature(&graph),
+            "System class > static com.example.Cache.sOther > com.example.Model"
+        );
+    }
+}
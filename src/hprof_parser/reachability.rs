@@ -0,0 +1,320 @@
+use crate::hprof_parser::graph::{HeapGraph, ReferenceStrength};
+use crate::hprof_parser::histogram::ClassStats;
+use crate::hprof_parser::snapshot::SubTag;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+/// How an object can be reached from the gc roots, from the strongest to the
+/// weakest: the weakest reference of a path decides how strong the path is,
+/// and the strongest path decides for the object.
+///
+/// As in `java.lang.ref`, an object is finalizer reachable once its soft and
+/// weak references are cleared and before its phantom references are
+/// enqueued, so it ranks between weak and phantom reachable ones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Reachability {
+    Strong,
+    Soft,
+    Weak,
+    Finalizer,
+    Phantom,
+    Unreachable,
+}
+
+impl Reachability {
+    /// Strongest first.
+    pub const ALL: [Reachability; 6] = [
+        Reachability::Strong,
+        Reachability::Soft,
+        Reachability::Weak,
+        Reachability::Finalizer,
+        Reachability::Phantom,
+        Reachability::Unreachable,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reachability::Strong => "strong",
+            Reachability::Soft => "soft",
+            Reachability::Weak => "weak",
+            Reachability::Finalizer => "finalizer",
+            Reachability::Phantom => "phantom",
+            Reachability::Unreachable => "unreachable",
+        }
+    }
+}
+
+impl From<ReferenceStrength> for Reachability {
+    fn from(strength: ReferenceStrength) -> Self {
+        match strength {
+            ReferenceStrength::Strong => Reachability::Strong,
+            ReferenceStrength::Soft => Reachability::Soft,
+            ReferenceStrength::Weak => Reachability::Weak,
+            ReferenceStrength::Phantom => Reachability::Phantom,
+            ReferenceStrength::Finalizer => Reachability::Finalizer,
+        }
+    }
+}
+
+/// Reachability of every object of a dump.
+#[derive(Debug, Clone)]
+pub struct ReachabilityMap {
+    reachability: HashMap<u64, Reachability>,
+}
+
+impl ReachabilityMap {
+    /// One traversal per class, strongest first. Each one only follows
+    /// references at least as strong as its class and starts from the
+    /// references the previous ones had to leave aside, so an object gets the
+    /// class of the first traversal that reaches it.
+    pub fn new(graph: &HeapGraph) -> Self {
+        let mut reachability: HashMap<u64, Reachability> = HashMap::new();
+        let mut deferred: BTreeMap<Reachability, Vec<u64>> = BTreeMap::new();
+        for root in graph.gc_roots() {
+            let Some(id) = root.object_id() else {
+                continue;
+            };
+            // objects pending finalization are only held by the finalizer
+            let level = match root {
+                SubTag::RootFinalizing(_) => Reachability::Finalizer,
+                _ => Reachability::Strong,
+            };
+            deferred.entry(level).or_default().push(id);
+        }
+
+        for level in Reachability::ALL {
+            let mut stack = deferred.remove(&level).unwrap_or_default();
+            while let Some(id) = stack.pop() {
+                if reachability.contains_key(&id) || graph.object(id).is_none() {
+                    continue;
+                }
+                reachability.insert(id, level);
+                for edge in graph.edges(id) {
+                    let edge_level = Reachability::from(edge.strength);
+                    if edge_level <= level {
+                        stack.push(edge.target);
+                    } else {
+                        deferred.entry(edge_level).or_default().push(edge.target);
+                    }
+                }
+            }
+        }
+        Self { reachability }
+    }
+
+    pub fn reachability(&self, id: u64) -> Reachability {
+        self.reachability
+            .get(&id)
+            .copied()
+            .unwrap_or(Reachability::Unreachable)
+    }
+
+    /// Per class name, the objects of each reachability class, sorted by
+    /// shallow size of the objects that are not strongly reachable, largest
+    /// first. Retained sizes are left out.
+    pub fn class_totals(&self, graph: &HeapGraph) -> ReachabilityHistogram {
+        let mut classes: HashMap<String, BTreeMap<Reachability, ClassStats>> = HashMap::new();
+        for subtag in graph.objects() {
+            let Some(id) = subtag.object_id() else {
+                continue;
+            };
+            let class_name = match subtag {
+                SubTag::ClassDump { .. } => "java.lang.Class".to_string(),
+                _ => match graph.type_name(id) {
+                    Some(name) => name,
+                    None => continue,
+                },
+            };
+            let stats = classes
+                .entry(class_name)
+                .or_default()
+                .entry(self.reachability(id))
+                .or_default();
+            stats.instance_count += 1;
+            stats.shallow_size += graph.shallow_size(id);
+        }
+
+        let mut rows: Vec<ClassReachability> = classes
+            .into_iter()
+            .map(|(class_name, stats)| ClassReachability { class_name, stats })
+            .collect();
+        rows.sort_by(|a, b| {
+            b.releasable_size()
+                .cmp(&a.releasable_size())
+                .then_with(|| a.class_name.cmp(&b.class_name))
+        });
+        ReachabilityHistogram { rows }
+    }
+}
+
+/// Objects of one class split by reachability.
+#[derive(Debug, Clone)]
+pub struct ClassReachability {
+    pub class_name: String,
+    pub stats: BTreeMap<Reachability, ClassStats>,
+}
+
+impl ClassReachability {
+    pub fn get(&self, reachability: Reachability) -> ClassStats {
+        self.stats.get(&reachability).copied().unwrap_or_default()
+    }
+
+    /// Shallow size of the objects that are not strongly reachable, what the
+    /// collector could reclaim under memory pressure.
+    pub fn releasable_size(&self) -> u64 {
+        self.stats
+            .iter()
+            .filter(|(reachability, _)| **reachability != Reachability::Strong)
+            .map(|(_, stats)| stats.shallow_size)
+            .sum()
+    }
+}
+
+/// Shallow size per reachability class, one row per class name.
+#[derive(Debug, Clone)]
+pub struct ReachabilityHistogram {
+    rows: Vec<ClassReachability>,
+}
+
+impl ReachabilityHistogram {
+    pub fn rows(&self) -> &[ClassReachability] {
+        &self.rows
+    }
+
+    pub fn row(&self, class_name: &str) -> Option<&ClassReachability> {
+        self.rows.iter().find(|row| row.class_name == class_name)
+    }
+
+    /// Totals of every class.
+    pub fn total(&self, reachability: Reachability) -> ClassStats {
+        let mut total = ClassStats::default();
+        for row in &self.rows {
+            let stats = row.get(reachability);
+            total.instance_count += stats.instance_count;
+            total.shallow_size += stats.shallow_size;
+        }
+        total
+    }
+}
+
+impl Display for ReachabilityHistogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for level in Reachability::ALL {
+            write!(f, "{:>12}", level.name())?;
+        }
+        writeln!(f, "  class")?;
+        for class in &self.rows {
+            for level in Reachability::ALL {
+                write!(f, "{:>12}", class.get(level).shallow_size)?;
+            }
+            writeln!(f, "  {}", class.class_name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::{JavaType, JavaValue};
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    #[test]
+    fn reachability_classes() {
+        let mut dump = DumpBuilder::new();
+        let reference = dump.class(
+            "java.lang.ref.Reference",
+            0,
+            &[("referent", JavaType::Object)],
+        );
+        let soft = dump.class("java.lang.ref.SoftReference", reference, &[]);
+        let weak = dump.class("java.lang.ref.WeakReference", reference, &[]);
+        let finalizer = dump.class("java.lang.ref.FinalizerReference", reference, &[]);
+        let phantom = dump.class("java.lang.ref.PhantomReference", reference, &[]);
+        let node = dump.class("com.example.Node", 0, &[("next", JavaType::Object)]);
+        let holder_class = dump.class("com.example.Holder", 0, &[("refs", JavaType::Object)]);
+
+        let mut objects = HashMap::new();
+        for name in [
+            "strong",
+            "soft",
+            "weak",
+            "finalizer",
+            "phantom",
+            "weak_and_finalizer",
+            "finalizer_and_phantom",
+            "weak_then_soft",
+            "finalizing",
+            "unreachable",
+        ] {
+            objects.insert(name, dump.instance(node, &[]));
+        }
+        let mut refs = Vec::new();
+        let mut reference_to = |dump: &mut DumpBuilder, class_id, name: &str| {
+            let id = dump.instance(class_id, &[("referent", JavaValue::Object(objects[name]))]);
+            refs.push(id);
+            id
+        };
+        reference_to(&mut dump, soft, "soft");
+        reference_to(&mut dump, weak, "weak");
+        reference_to(&mut dump, finalizer, "finalizer");
+        reference_to(&mut dump, phantom, "phantom");
+        // the strongest path wins
+        reference_to(&mut dump, finalizer, "weak_and_finalizer");
+        reference_to(&mut dump, weak, "weak_and_finalizer");
+        reference_to(&mut dump, phantom, "finalizer_and_phantom");
+        reference_to(&mut dump, finalizer, "finalizer_and_phantom");
+        // and the weakest reference of a path decides for it
+        let soft_ref = dump.instance(
+            soft,
+            &[("referent", JavaValue::Object(objects["weak_then_soft"]))],
+        );
+        let middle = dump.instance(node, &[("next", JavaValue::Object(soft_ref))]);
+        let weak_ref = dump.instance(weak, &[("referent", JavaValue::Object(middle))]);
+        refs.push(weak_ref);
+
+        let array_class = dump.class("java.lang.Object[]", 0, &[]);
+        let array = dump.object_array(array_class, &refs);
+        let holder = dump.instance(holder_class, &[("refs", JavaValue::Object(array))]);
+        dump.root(holder);
+        dump.root(objects["strong"]);
+        dump.finalizing_root(objects["finalizing"]);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let map = ReachabilityMap::new(&graph);
+        for (name, expected) in [
+            ("strong", Reachability::Strong),
+            ("soft", Reachability::Soft),
+            ("weak", Reachability::Weak),
+            ("finalizer", Reachability::Finalizer),
+            ("phantom", Reachability::Phantom),
+            ("weak_and_finalizer", Reachability::Weak),
+            ("finalizer_and_phantom", Reachability::Finalizer),
+            ("weak_then_soft", Reachability::Weak),
+            ("finalizing", Reachability::Finalizer),
+            ("unreachable", Reachability::Unreachable),
+        ] {
+            assert_eq!(map.reachability(objects[name]), expected, "{}", name);
+        }
+        assert_eq!(map.reachability(middle), Reachability::Weak);
+        assert_eq!(map.reachability(soft_ref), Reachability::Weak);
+        assert_eq!(map.reachability(holder), Reachability::Strong);
+
+        let histogram = map.class_totals(&graph);
+        let size = graph.shallow_size(objects["strong"]);
+        let row = histogram.row("com.example.Node").unwrap();
+        let counts: Vec<u64> = Reachability::ALL
+            .iter()
+            .map(|level| row.get(*level).instance_count)
+            .collect();
+        // middle is weakly reachable
+        assert_eq!(counts, vec![1, 1, 4, 3, 1, 1]);
+        assert_eq!(row.releasable_size(), 10 * size);
+        assert_eq!(histogram.total(Reachability::Soft).instance_count, 1);
+        assert_eq!(
+            histogram.total(Reachability::Weak).shallow_size,
+            4 * size + graph.shallow_size(soft_ref)
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::hprof_parser::snapshot::JavaType;
+
+/// How a runtime lays objects out in memory.
+///
+/// Apart from the instance sizes of ART, a dump only tells us which fields
+/// and elements an object has, the size model turns that into the number of
+/// bytes the object really occupies. The presets cover the common runtimes,
+/// anything else can be described by filling in the fields directly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SizeModel {
+    /// class pointer + lock word in front of every object
+    pub object_header: u32,
+    /// length slot that follows the header of an array
+    pub array_length: u32,
+    /// size of a reference held in a field or an array slot
+    pub reference_size: u32,
+    /// every object is rounded up to a multiple of this
+    pub alignment: u32,
+}
+
+impl SizeModel {
+    /// ART on a 64-bit device: 4 byte `klass_` + 4 byte `monitor_`, 8 byte
+    /// alignment. Heap references are always compressed to 32 bits.
+    pub const ART_64: SizeModel = SizeModel {
+        object_header: 8,
+        array_length: 4,
+        reference_size: 4,
+        alignment: 8,
+    };
+
+    /// ART on a 32-bit device, laid out like [`SizeModel::ART_64`] since
+    /// references are 32 bits on both.
+    pub const ART_32: SizeModel = SizeModel::ART_64;
+
+    /// HotSpot 64-bit with compressed oops and compressed class pointers.
+    pub const HOTSPOT_COMPRESSED_OOPS: SizeModel = SizeModel {
+        object_header: 12,
+        array_length: 4,
+        reference_size: 4,
+        alignment: 8,
+    };
+
+    pub fn type_size(&self, java_type: JavaType) -> u32 {
+        match java_type {
+            JavaType::Object => self.reference_size,
+            JavaType::Boolean | JavaType::Byte => 1,
+            JavaType::Char | JavaType::Short => 2,
+            JavaType::Float | JavaType::Int => 4,
+            JavaType::Double | JavaType::Long => 8,
+        }
+    }
+
+    pub fn align(&self, size: u64) -> u64 {
+        align_up(size, self.alignment as u64)
+    }
+
+    /// `field_bytes` is the sum of every instance field of the class and its supers.
+    pub fn instance_size(&self, field_bytes: u64) -> u64 {
+        self.align(self.object_header as u64 + field_bytes)
+    }
+
+    pub fn object_array_size(&self, length: usize) -> u64 {
+        self.array_size(self.reference_size, length)
+    }
+
+    pub fn primitive_array_size(&self, element_type: JavaType, length: usize) -> u64 {
+        self.array_size(self.type_size(element_type), length)
+    }
+
+    /// `static_bytes` is the sum of the static fields of the class,
+    /// `class_instance_size` the shallow size of a `java.lang.Class` instance.
+    pub fn class_size(&self, class_instance_size: u64, static_bytes: u64) -> u64 {
+        self.align(class_instance_size.max(self.object_header as u64) + static_bytes)
+    }
+
+    fn array_size(&self, element_size: u32, length: usize) -> u64 {
+        // elements start at an offset aligned to their own size, e.g. long[] on ART starts at 16
+        let data_offset = align_up(
+            (self.object_header + self.array_length) as u64,
+            element_size.min(8) as u64,
+        );
+        self.align(data_offset + element_size as u64 * length as u64)
+    }
+}
+
+impl Default for SizeModel {
+    fn default() -> Self {
+        SizeModel::ART_64
+    }
+}
+
+fn align_up(size: u64, alignment: u64) -> u64 {
+    if alignment <= 1 {
+        return size;
+    }
+    size.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_are_aligned() {
+        let model = SizeModel::ART_64;
+        assert_eq!(model.instance_size(0), 8);
+        assert_eq!(model.instance_size(4), 16);
+        assert_eq!(model.instance_size(8), 16);
+        assert_eq!(SizeModel::HOTSPOT_COMPRESSED_OOPS.instance_size(4), 16);
+        assert_eq!(SizeModel::HOTSPOT_COMPRESSED_OOPS.instance_size(8), 24);
+    }
+
+    #[test]
+    fn array_elements_start_aligned_to_their_size() {
+        let model = SizeModel::ART_64;
+        assert_eq!(model.primitive_array_size(JavaType::Byte, 0), 16);
+        assert_eq!(model.primitive_array_size(JavaType::Byte, 5), 24);
+        assert_eq!(model.primitive_array_size(JavaType::Char, 4), 24);
+        assert_eq!(model.primitive_array_size(JavaType::Long, 1), 24);
+        assert_eq!(model.object_array_size(3), 24);
+        assert_eq!(
+            SizeModel::HOTSPOT_COMPRESSED_OOPS.primitive_array_size(JavaType::Long, 1),
+            24
+        );
+    }
+
+    #[test]
+    fn classes_add_their_statics() {
+        let model = SizeModel::ART_64;
+        assert_eq!(model.class_size(0, 0), 8);
+        assert_eq!(model.class_size(120, 4), 128);
+        assert_eq!(model.class_size(120, 12), 136);
+    }
+
+    #[test]
+    fn no_alignment() {
+        let model = SizeModel {
+            alignment: 1,
+            ..SizeModel::ART_32
+        };
+        assert_eq!(model.instance_size(3), 11);
+        assert_eq!(model.primitive_array_size(JavaType::Byte, 3), 15);
+    }
+}
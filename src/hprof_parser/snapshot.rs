@@ -52,14 +52,14 @@ pub trait HprofRead {
     }
 }
 
-struct Slice<'a> {
+pub(crate) struct Slice<'a> {
     buf: &'a [u8],
     id_size: u32,
     n: Cell<usize>,
 }
 
 impl<'a> Slice<'a> {
-    fn new(buf: &'a [u8], size: u32) -> Self {
+    pub(crate) fn new(buf: &'a [u8], size: u32) -> Self {
         Self {
             buf,
             id_size: size,
@@ -67,6 +67,25 @@ impl<'a> Slice<'a> {
         }
     }
 
+    /// Same as [`HprofRead::read_u8_array`], but the returned slice borrows the
+    /// underlying buffer instead of `self`, so it can outlive the reader.
+    fn read_bytes(&self, size: usize) -> Result<&'a [u8]> {
+        self.check(size)?;
+        let n = self.n.get();
+        let arr = &self.buf[n..n + size];
+        self.n.set(n + size);
+        Ok(arr)
+    }
+
+    /// What is left to read, without consuming it.
+    fn rest(&self) -> &'a [u8] {
+        &self.buf[self.n.get()..]
+    }
+
+    fn read_str(&self, size: usize) -> Result<&'a str> {
+        Ok(str::from_utf8(self.read_bytes(size)?)?)
+    }
+
     fn check(&self, need: usize) -> Result<()> {
         let remain = self.remain();
         if remain < need {
@@ -130,16 +149,11 @@ impl<'a> HprofRead for Slice<'a> {
     }
 
     fn read_u8_array(&self, size: usize) -> Result<&[u8]> {
-        self.check(size)?;
-        let n = self.n.get();
-        let arr = &self.buf[n..n + size];
-        self.n.set(n + size);
-        return Ok(arr);
+        self.read_bytes(size)
     }
 
     fn read_utf8(&self, size: usize) -> Result<&str> {
-        let char_array = self.read_u8_array(size)?;
-        Ok(str::from_utf8(char_array)?)
+        self.read_str(size)
     }
 
     fn skip(&self, size: usize) -> Result<()> {
@@ -194,6 +208,11 @@ impl<'a> Snapshot<'a> {
     pub fn header(&self) -> &HprofHeader {
         &self.header
     }
+
+    /// Consumes the snapshot and hands out the records parsed so far.
+    pub fn into_records(self) -> Vec<Record<'a>> {
+        self.records.into_inner()
+    }
 }
 
 impl<'a> HprofRead for Snapshot<'a> {
@@ -309,7 +328,7 @@ pub enum Record<'a> {
 }
 
 impl<'a> Snapshot<'a> {
-    pub fn parse_records(&'a self) -> Result<()> {
+    pub fn parse_records(&self) -> Result<()> {
         let total = self.remain();
         let mut count = 0;
 
@@ -322,7 +341,7 @@ impl<'a> Snapshot<'a> {
         Ok(())
     }
 
-    fn parse_record(&'a self) -> Result<usize> {
+    fn parse_record(&self) -> Result<usize> {
         let before = self.remain();
 
         let tag = self.read_u8()?;
@@ -333,7 +352,7 @@ impl<'a> Snapshot<'a> {
         let record = match tag {
             constant::TAG_STRING => Record::String {
                 id: self.read_id()?,
-                content: self.read_utf8(len - self.id_size())?,
+                content: self.slice.read_str(len - self.id_size())?,
             },
 
             constant::TAG_LOAD_CLASS => Record::LoadClass {
@@ -360,8 +379,8 @@ impl<'a> Snapshot<'a> {
                 let len = self.read_u32()? as usize;
                 let mut v = Vec::<u64>::with_capacity(len);
 
-                for i in &mut v {
-                    *i = self.read_id()?;
+                for _ in 0..len {
+                    v.push(self.read_id()?);
                 }
 
                 Record::StackTrace {
@@ -387,7 +406,7 @@ impl<'a> Snapshot<'a> {
 
             _ => Record::Unknown {
                 tag,
-                content: self.read_u8_array(len)?,
+                content: self.slice.read_bytes(len)?,
             },
         };
         self.records.borrow_mut().push(record);
@@ -395,7 +414,7 @@ impl<'a> Snapshot<'a> {
         Ok(before - self.remain())
     }
 
-    fn parse_subtag(&self) -> Result<(SubTag, usize)> {
+    fn parse_subtag(&self) -> Result<(SubTag<'a>, usize)> {
         let before = self.remain();
         match self.read_u8()? {
             0xFF => Ok(SubTag::RootUnknown(self.read_id()?)),
@@ -468,7 +487,7 @@ impl<'a> Snapshot<'a> {
                 let stack_trace_serial_number = self.read_u32()?;
                 let class_object_id = self.read_id()?;
                 let count: usize = self.read_u32()? as _;
-                let instance_field_values = self.read_u8_array(count)?;
+                let instance_field_values = self.slice.read_bytes(count)?;
 
                 Ok(SubTag::InstanceDump {
                     object_id,
@@ -502,20 +521,42 @@ impl<'a> Snapshot<'a> {
                 let stack_trace_serial_number = self.read_u32()?;
                 let count = self.read_u32()?;
                 let element_type: JavaType = self.read_u8()?.try_into()?;
+                let rest = self.slice.rest();
                 let mut elements: Vec<JavaValue> = Vec::new();
 
                 for _ in 0..count {
                     elements.push(JavaValue::parse(self, element_type)?)
                 }
+                let raw_elements = &rest[..rest.len() - self.remain()];
 
                 Ok(SubTag::PrimitiveArrayDump {
                     array_object_id,
                     stack_trace_serial_number,
                     element_type,
                     elements,
+                    raw_elements,
                 })
             }
 
+            0x89 => Ok(SubTag::RootInternedString(self.read_id()?)),
+
+            0x8A => Ok(SubTag::RootFinalizing(self.read_id()?)),
+
+            0x8B => Ok(SubTag::RootDebugger(self.read_id()?)),
+
+            0x8C => Ok(SubTag::RootReferenceCleanup(self.read_id()?)),
+
+            0x8D => Ok(SubTag::RootVmInternal(self.read_id()?)),
+
+            0x8E => Ok(SubTag::RootJniMonitor(Object::parse(self)?)),
+
+            0x90 => Ok(SubTag::Unreachable(self.read_id()?)),
+
+            0xFE => Ok(SubTag::HeapDumpInfo {
+                heap_id: self.read_u32()?,
+                heap_name_id: self.read_id()?,
+            }),
+
             tag => Err(Error::UnknownSubTag(tag)),
         }
         .map(|subtag| (subtag, before - self.remain()))
@@ -576,13 +617,128 @@ pub enum SubTag<'a> {
         stack_trace_serial_number: u32,
         element_type: JavaType,
         elements: Vec<JavaValue>,
+        /// `elements` as dumped, big endian
+        raw_elements: &'a [u8],
+    },
+
+    // android
+    RootInternedString(u64),
+
+    RootFinalizing(u64),
+
+    RootDebugger(u64),
+
+    RootReferenceCleanup(u64),
+
+    RootVmInternal(u64),
+
+    RootJniMonitor(Object),
+
+    Unreachable(u64),
+
+    /// every subtag after this one belongs to the heap it names (app, image, zygote)
+    HeapDumpInfo {
+        heap_id: u32,
+        heap_name_id: u64,
     },
 }
 
+impl<'a> SubTag<'a> {
+    /// The object a root points at, or the id of a dumped object.
+    pub fn object_id(&self) -> Option<u64> {
+        match self {
+            SubTag::RootUnknown(id)
+            | SubTag::RootStickyClass(id)
+            | SubTag::RootMonitorUsed(id)
+            | SubTag::RootInternedString(id)
+            | SubTag::RootFinalizing(id)
+            | SubTag::RootDebugger(id)
+            | SubTag::RootReferenceCleanup(id)
+            | SubTag::RootVmInternal(id)
+            | SubTag::Unreachable(id) => Some(*id),
+            SubTag::RootJniGlobal { object_id, .. } => Some(*object_id),
+            SubTag::RootJniLocal(o)
+            | SubTag::RootJavaFrame(o)
+            | SubTag::RootThreadObject(o)
+            | SubTag::RootJniMonitor(o) => Some(o.object_id),
+            SubTag::RootNativeStack(o) | SubTag::RootThreadBlock(o) => Some(o.object_id),
+            SubTag::ClassDump {
+                class_object_id, ..
+            } => Some(*class_object_id),
+            SubTag::InstanceDump { object_id, .. } => Some(*object_id),
+            SubTag::ObjectArrayDump {
+                array_object_id, ..
+            }
+            | SubTag::PrimitiveArrayDump {
+                array_object_id, ..
+            } => Some(*array_object_id),
+            SubTag::HeapDumpInfo { .. } => None,
+        }
+    }
+
+    /// Human readable kind of a gc root, `None` if this is not a root.
+    pub fn root_name(&self) -> Option<&'static str> {
+        Some(match self {
+            SubTag::RootUnknown(_) => "Unknown",
+            SubTag::RootJniGlobal { .. } => "Global variable in native code",
+            SubTag::RootJniLocal(_) => "Local variable in native code",
+            SubTag::RootJavaFrame(_) => "Java local variable",
+            SubTag::RootNativeStack(_) => "Input or output parameters in native code",
+            SubTag::RootStickyClass(_) => "System class",
+            SubTag::RootThreadBlock(_) => "Thread block",
+            SubTag::RootMonitorUsed(_) => "Monitor (anything that called the wait() or notify() methods, or that is synchronized.)",
+            SubTag::RootThreadObject(_) => "Thread object",
+            SubTag::RootInternedString(_) => "Interned string",
+            SubTag::RootFinalizing(_) => "Finalizing",
+            SubTag::RootDebugger(_) => "Debugger",
+            SubTag::RootReferenceCleanup(_) => "Reference cleanup",
+            SubTag::RootVmInternal(_) => "VM internal",
+            SubTag::RootJniMonitor(_) => "JNI monitor",
+            SubTag::Unreachable(_) => "Unreachable",
+            _ => return None,
+        })
+    }
+
+    /// Serial number of the STACK_TRACE where the object was allocated, 0 or
+    /// a trace without frames when allocations were not tracked.
+    pub fn stack_trace_serial_number(&self) -> Option<u32> {
+        match self {
+            SubTag::ClassDump {
+                stack_trace_serial_number,
+                ..
+            }
+            | SubTag::InstanceDump {
+                stack_trace_serial_number,
+                ..
+            }
+            | SubTag::ObjectArrayDump {
+                stack_trace_serial_number,
+                ..
+            }
+            | SubTag::PrimitiveArrayDump {
+                stack_trace_serial_number,
+                ..
+            } => Some(*stack_trace_serial_number),
+            _ => None,
+        }
+    }
+
+    /// Whether this subtag is a dumped object rather than a root.
+    pub fn is_object(&self) -> bool {
+        matches!(
+            self,
+            SubTag::ClassDump { .. }
+                | SubTag::InstanceDump { .. }
+                | SubTag::ObjectArrayDump { .. }
+                | SubTag::PrimitiveArrayDump { .. }
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct InstantField {
-    name_string_id: u64,
-    java_type: JavaType,
+    pub name_string_id: u64,
+    pub java_type: JavaType,
 }
 
 impl InstantField {
@@ -596,8 +752,8 @@ impl InstantField {
 
 #[derive(Debug)]
 pub struct StaticField {
-    name_string_id: u64,
-    java_value: JavaValue,
+    pub name_string_id: u64,
+    pub java_value: JavaValue,
 }
 
 impl StaticField {
@@ -611,8 +767,8 @@ impl StaticField {
 
 #[derive(Debug)]
 pub struct Constant {
-    constant_pool_index: u32,
-    java_value: JavaValue,
+    pub constant_pool_index: u32,
+    pub java_value: JavaValue,
 }
 
 impl Constant {
@@ -624,7 +780,7 @@ impl Constant {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum JavaType {
     Object = 2,
     Boolean = 4,
@@ -637,6 +793,23 @@ pub enum JavaType {
     Long = 11,
 }
 
+impl JavaType {
+    /// Java source name, e.g. `int`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JavaType::Object => "java.lang.Object",
+            JavaType::Boolean => "boolean",
+            JavaType::Char => "char",
+            JavaType::Float => "float",
+            JavaType::Double => "double",
+            JavaType::Byte => "byte",
+            JavaType::Short => "short",
+            JavaType::Int => "int",
+            JavaType::Long => "long",
+        }
+    }
+}
+
 impl TryFrom<u8> for JavaType {
     type Error = Error;
 
@@ -656,10 +829,10 @@ impl TryFrom<u8> for JavaType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum JavaValue {
     // 2
-    Object(u32),
+    Object(u64),
     // 4
     Boolean(bool),
     // 5
@@ -679,13 +852,27 @@ pub enum JavaValue {
 }
 
 impl JavaValue {
+    pub fn java_type(&self) -> JavaType {
+        match self {
+            JavaValue::Object(_) => JavaType::Object,
+            JavaValue::Boolean(_) => JavaType::Boolean,
+            JavaValue::Char(_) => JavaType::Char,
+            JavaValue::Float(_) => JavaType::Float,
+            JavaValue::Double(_) => JavaType::Double,
+            JavaValue::Byte(_) => JavaType::Byte,
+            JavaValue::Short(_) => JavaType::Short,
+            JavaValue::Int(_) => JavaType::Int,
+            JavaValue::Long(_) => JavaType::Long,
+        }
+    }
+
     fn parse_with_type<R: HprofRead>(r: &R) -> Result<Self> {
         Self::parse(r, JavaType::try_from(r.read_u8()?)?)
     }
 
-    fn parse<R: HprofRead>(r: &R, ty: JavaType) -> Result<Self> {
+    pub(crate) fn parse<R: HprofRead>(r: &R, ty: JavaType) -> Result<Self> {
         Ok(match ty {
-            JavaType::Object => JavaValue::Object(r.read_u32()?),
+            JavaType::Object => JavaValue::Object(r.read_id()?),
             JavaType::Boolean => JavaValue::Boolean(r.read_u8()? != 0),
             JavaType::Char => JavaValue::Char(r.read_u16()?),
             JavaType::Float => JavaValue::Float(r.read_f32()?),
@@ -700,9 +887,9 @@ impl JavaValue {
 
 #[derive(Debug)]
 pub struct Object {
-    object_id: u64,
-    thread_serial_number: u32,
-    frame_number_in_stack_trace: i32,
+    pub object_id: u64,
+    pub thread_serial_number: u32,
+    pub frame_number_in_stack_trace: i32,
 }
 
 impl Object {
@@ -717,8 +904,8 @@ impl Object {
 
 #[derive(Debug)]
 pub struct NativeObject {
-    object_id: u64,
-    thread_serial_number: u32,
+    pub object_id: u64,
+    pub thread_serial_number: u32,
 }
 
 impl NativeObject {
@@ -0,0 +1,488 @@
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::path::describe_referrer;
+use crate::hprof_parser::snapshot::{JavaType, JavaValue, SubTag};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// How many holders are kept for each duplicated value.
+const TOP_HOLDERS: usize = 5;
+
+/// `java.lang.String` instances sharing the same content.
+#[derive(Debug, Clone)]
+pub struct DuplicateString {
+    pub value: String,
+    pub string_ids: Vec<u64>,
+    /// string + characters of one copy
+    pub bytes_per_copy: u64,
+    /// what interning every copy but one would save
+    pub wasted_bytes: u64,
+    /// `com.foo.Model.name` -> number of copies held that way, most common first
+    pub top_holders: Vec<(String, usize)>,
+}
+
+impl DuplicateString {
+    pub fn copies(&self) -> usize {
+        self.string_ids.len()
+    }
+}
+
+impl Display for DuplicateString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:?}: {} copies, {} bytes wasted",
+            self.value,
+            self.copies(),
+            self.wasted_bytes
+        )?;
+        for (holder, count) in &self.top_holders {
+            writeln!(f, "    {} x{}", holder, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Groups strings by content, sorted by wasted bytes, largest first.
+pub fn find_duplicate_strings(graph: &HeapGraph) -> Vec<DuplicateString> {
+    let mut by_value: HashMap<Cow<str>, Vec<u64>> = HashMap::new();
+    // backing array -> strings using it, to know which arrays interning frees
+    let mut array_users: HashMap<u64, usize> = HashMap::new();
+    for id in graph.instances_of("java.lang.String") {
+        if let Some(value) = string_value(graph, id) {
+            by_value.entry(value).or_default().push(id);
+        }
+        if let (_, Some(array)) = string_size(graph, id) {
+            *array_users.entry(array).or_default() += 1;
+        }
+    }
+    by_value.retain(|_, ids| ids.len() > 1);
+
+    let duplicated: HashSet<u64> = by_value.values().flatten().copied().collect();
+    let referrers = graph.referrers(&duplicated);
+
+    let mut duplicates: Vec<DuplicateString> = by_value
+        .into_iter()
+        .map(|(value, mut string_ids)| {
+            string_ids.sort_unstable();
+            let (kept_size, kept_array) = string_size(graph, string_ids[0]);
+            let bytes_per_copy =
+                kept_size + kept_array.map_or(0, |array| graph.shallow_size(array));
+
+            // copies sharing a backing array, e.g. substrings on old releases,
+            // only free it when no string outside the copies still uses it
+            let mut group_users: HashMap<u64, usize> = HashMap::new();
+            let mut wasted_bytes = 0;
+            for id in &string_ids[1..] {
+                let (size, array) = string_size(graph, *id);
+                wasted_bytes += size;
+                if let Some(array) = array {
+                    *group_users.entry(array).or_default() += 1;
+                }
+            }
+            if let Some(array) = kept_array {
+                group_users.remove(&array);
+            }
+            wasted_bytes += group_users
+                .into_iter()
+                .filter(|(array, users)| array_users.get(array) == Some(users))
+                .map(|(array, _)| graph.shallow_size(array))
+                .sum::<u64>();
+
+            let mut holders: HashMap<String, usize> = HashMap::new();
+            for id in &string_ids {
+                for (from, edge) in referrers.get(id).into_iter().flatten() {
+                    *holders
+                        .entry(describe_referrer(graph, *from, *edge))
+                        .or_default() += 1;
+                }
+            }
+            let mut top_holders: Vec<(String, usize)> = holders.into_iter().collect();
+            top_holders.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            top_holders.truncate(TOP_HOLDERS);
+
+            DuplicateString {
+                wasted_bytes,
+                value: value.into_owned(),
+                string_ids,
+                bytes_per_copy,
+                top_holders,
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| {
+        b.wasted_bytes
+            .cmp(&a.wasted_bytes)
+            .then_with(|| a.value.cmp(&b.value))
+    });
+    duplicates
+}
+
+/// Content of a `java.lang.String` instance, `None` if `id` is not one or its
+/// backing array is missing from the dump. Handles the layouts of:
+/// - ART since Android 8: `value` is a `byte[]` of Latin-1 when the string is
+///   compressed and a `char[]` of UTF-16 otherwise, `count` holds the length
+///   shifted left by one with the low bit cleared for compressed strings
+/// - older ART and JDK 6: a `char[]` shared between strings, with `offset`
+///   and `count`
+/// - JDK 9+: a `byte[]` with `coder` 0 for Latin-1 and 1 for UTF-16
+/// - JDK 7 and 8: a `char[]` holding exactly the content
+///
+/// ASCII content is borrowed from the dump, other Latin-1 and UTF-16 content
+/// is decoded into a new string.
+pub fn string_value<'a>(graph: &HeapGraph<'a>, id: u64) -> Option<Cow<'a, str>> {
+    let Some(JavaValue::Object(value)) = graph.field(id, "value") else {
+        return None;
+    };
+    let (element_type, length, bytes): (JavaType, usize, &'a [u8]) = match graph.object(value) {
+        Some(SubTag::PrimitiveArrayDump {
+            element_type,
+            elements,
+            raw_elements,
+            ..
+        }) => (*element_type, elements.len(), *raw_elements),
+        _ if value == 0 => (JavaType::Char, 0, &[]),
+        _ => return None,
+    };
+    let int_field = |name| match graph.field(id, name) {
+        Some(JavaValue::Int(value)) => Some(value.max(0) as usize),
+        _ => None,
+    };
+    let count = int_field("count");
+    let offset = int_field("offset");
+    let coder = match graph.field(id, "coder") {
+        Some(JavaValue::Byte(coder)) => Some(coder),
+        _ => None,
+    };
+    let is_bytes = element_type == JavaType::Byte;
+
+    let (start, count) = match (offset, count, coder) {
+        (Some(offset), Some(count), _) => (offset, count),
+        (None, _, Some(1)) if is_bytes => return Some(decode_utf16_bytes(bytes)),
+        (None, Some(count), _) if is_bytes => (0, count >> 1),
+        (None, Some(count), _) if count & 1 == 1 && count >> 1 == length => (0, count >> 1),
+        (None, Some(count), _) => (0, count),
+        (_, None, _) => (0, length),
+    };
+    let start = start.min(length);
+    let end = (start + count).min(length);
+    if start == end {
+        return Some(Cow::Borrowed(""));
+    }
+
+    match element_type {
+        JavaType::Byte => Some(decode_latin1(&bytes[start..end])),
+        JavaType::Char => {
+            let units: Vec<u16> = bytes[2 * start..2 * end]
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            Some(Cow::Owned(String::from_utf16_lossy(&units)))
+        }
+        _ => None,
+    }
+}
+
+/// Latin-1 is only valid UTF-8 while every byte is ASCII.
+fn decode_latin1(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) if bytes.is_ascii() => Cow::Borrowed(text),
+        _ => Cow::Owned(bytes.iter().map(|b| *b as char).collect()),
+    }
+}
+
+/// JDK 9+ UTF-16 strings store each char as two bytes in the byte order of
+/// the machine that took the dump, little endian in practice.
+fn decode_utf16_bytes(bytes: &[u8]) -> Cow<'static, str> {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    Cow::Owned(String::from_utf16_lossy(&units))
+}
+
+/// Size of a string without its backing array, and that array when it is a
+/// real object that copies may share.
+///
+/// Since Android 6 ART keeps the characters inside the String and the dump
+/// makes up a `value` array holding them: only the `count` characters are
+/// counted, one byte each when compressed and two otherwise. On other
+/// layouts the array is a real object.
+fn string_size(graph: &HeapGraph, id: u64) -> (u64, Option<u64>) {
+    let Some(JavaValue::Object(value)) = graph.field(id, "value") else {
+        return (graph.shallow_size(id), None);
+    };
+    let count = match graph.field(id, "count") {
+        Some(JavaValue::Int(count)) if graph.field(id, "offset").is_none() => count.max(0) as usize,
+        _ if value == 0 => return (graph.shallow_size(id), None),
+        _ => return (graph.shallow_size(id), Some(value)),
+    };
+    let payload = match graph.object(value) {
+        Some(SubTag::PrimitiveArrayDump {
+            element_type: JavaType::Byte,
+            elements,
+            ..
+        }) => (count >> 1).min(elements.len()),
+        Some(SubTag::PrimitiveArrayDump { elements, .. }) => {
+            // Android 8+ flags uncompressed strings with the low bit
+            let length = if count & 1 == 1 && count >> 1 == elements.len() {
+                count >> 1
+            } else {
+                count
+            };
+            2 * length.min(elements.len())
+        }
+        _ => 0,
+    };
+    let size = graph
+        .size_model()
+        .align(graph.shallow_size(id) + payload as u64);
+    (size, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    fn bytes(text: &[u8]) -> Vec<JavaValue> {
+        text.iter().map(|b| JavaValue::Byte(*b as i8)).collect()
+    }
+
+    fn chars(text: &str) -> Vec<JavaValue> {
+        text.encode_utf16().map(JavaValue::Char).collect()
+    }
+
+    /// Strings of the Android 8+ layout, `count` holding the length shifted
+    /// left with the low bit set when uncompressed.
+    #[test]
+    fn art_strings() {
+        let mut dump = DumpBuilder::new();
+        let fields = [
+            ("count", JavaType::Int),
+            ("hash", JavaType::Int),
+            ("value", JavaType::Object),
+        ];
+        let string = dump.class("java.lang.String", 0, &fields);
+        let array = dump.primitive_array(JavaType::Byte, &bytes(b"hello"));
+        let compressed = dump.instance(
+            string,
+            &[
+                ("count", JavaValue::Int(5 << 1)),
+                ("value", JavaValue::Object(array)),
+            ],
+        );
+        let array = dump.primitive_array(JavaType::Byte, &bytes(b"caf\xe9"));
+        let latin1 = dump.instance(
+            string,
+            &[
+                ("count", JavaValue::Int(4 << 1)),
+                ("value", JavaValue::Object(array)),
+            ],
+        );
+        let array = dump.primitive_array(JavaType::Char, &chars("héllo ✓"));
+        let uncompressed = dump.instance(
+            string,
+            &[
+                ("count", JavaValue::Int(7 << 1 | 1)),
+                ("value", JavaValue::Object(array)),
+            ],
+        );
+        let array = dump.primitive_array(JavaType::Byte, &[]);
+        let empty = dump.instance(
+            string,
+            &[
+                ("count", JavaValue::Int(0)),
+                ("value", JavaValue::Object(array)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let value = string_value(&graph, compressed).unwrap();
+        assert_eq!(value, "hello");
+        assert!(matches!(value, Cow::Borrowed(_)));
+        let value = string_value(&graph, latin1).unwrap();
+        assert_eq!(value, "café");
+        assert!(matches!(value, Cow::Owned(_)));
+        assert_eq!(string_value(&graph, uncompressed).unwrap(), "héllo ✓");
+        assert_eq!(string_value(&graph, empty).unwrap(), "");
+        assert_eq!(string_value(&graph, array), None);
+    }
+
+    #[test]
+    fn jdk_strings() {
+        let mut dump = DumpBuilder::new();
+        let fields = [
+            ("value", JavaType::Object),
+            ("offset", JavaType::Int),
+            ("count", JavaType::Int),
+        ];
+        let shared = dump.class("java.lang.String", 0, &fields);
+        let array = dump.primitive_array(JavaType::Char, &chars("xxhello worldyy"));
+        let substring = dump.instance(
+            shared,
+            &[
+                ("value", JavaValue::Object(array)),
+                ("offset", JavaValue::Int(2)),
+                ("count", JavaValue::Int(11)),
+            ],
+        );
+        let fields = [("value", JavaType::Object), ("coder", JavaType::Byte)];
+        let coded = dump.class("java.lang.String", 0, &fields);
+        let array = dump.primitive_array(JavaType::Byte, &bytes(b"latin"));
+        let latin1 = dump.instance(coded, &[("value", JavaValue::Object(array))]);
+        let utf16: Vec<u8> = "h✓".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let array = dump.primitive_array(JavaType::Byte, &bytes(&utf16));
+        let utf16 = dump.instance(
+            coded,
+            &[
+                ("value", JavaValue::Object(array)),
+                ("coder", JavaValue::Byte(1)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        assert_eq!(string_value(&graph, substring).unwrap(), "hello world");
+        assert_eq!(string_value(&graph, latin1).unwrap(), "latin");
+        assert_eq!(string_value(&graph, utf16).unwrap(), "h✓");
+    }
+
+    #[test]
+    fn art_string_size() {
+        let mut dump = DumpBuilder::new();
+        let fields = [("count", JavaType::Int), ("value", JavaType::Object)];
+        let string = dump.class("java.lang.String", 0, &fields);
+        let array = dump.primitive_array(JavaType::Byte, &bytes(b"hello"));
+        let compressed = dump.instance(
+            string,
+            &[
+                ("count", JavaValue::Int(5 << 1)),
+                ("value", JavaValue::Object(array)),
+            ],
+        );
+        let array = dump.primitive_array(JavaType::Char, &chars("héllo ✓"));
+        let uncompressed = dump.instance(
+            string,
+            &[
+                ("count", JavaValue::Int(7 << 1 | 1)),
+                ("value", JavaValue::Object(array)),
+            ],
+        );
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+        let model = graph.size_model();
+
+        let (size, array) = string_size(&graph, compressed);
+        assert_eq!(size, model.align(graph.shallow_size(compressed) + 5));
+        assert_eq!(size % 8, 0);
+        assert_eq!(array, None);
+        let (size, array) = string_size(&graph, uncompressed);
+        assert_eq!(size, model.align(graph.shallow_size(uncompressed) + 14));
+        assert_eq!(array, None);
+    }
+
+    #[test]
+    fn art_duplicates() {
+        let mut dump = DumpBuilder::new();
+        let fields = [("count", JavaType::Int), ("value", JavaType::Object)];
+        let string = dump.class("java.lang.String", 0, &fields);
+        let mut copies = Vec::new();
+        for text in ["hello", "hello", "hello", "unique"] {
+            let array = dump.primitive_array(JavaType::Byte, &bytes(text.as_bytes()));
+            copies.push(dump.instance(
+                string,
+                &[
+                    ("count", JavaValue::Int((text.len() as i32) << 1)),
+                    ("value", JavaValue::Object(array)),
+                ],
+            ));
+        }
+        let model = dump.class("com.example.Model", 0, &[("name", JavaType::Object)]);
+        for copy in &copies[..2] {
+            let instance = dump.instance(model, &[("name", JavaValue::Object(*copy))]);
+            dump.root(instance);
+        }
+        let holder = dump.class_with_statics(
+            "com.example.Holder",
+            0,
+            &[],
+            &[("sName", JavaValue::Object(copies[2]))],
+        );
+        dump.sticky_class(holder);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let duplicates = find_duplicate_strings(&graph);
+        assert_eq!(duplicates.len(), 1);
+        let duplicate = &duplicates[0];
+        assert_eq!(duplicate.value, "hello");
+        assert_eq!(duplicate.string_ids, copies[..3].to_vec());
+        assert_eq!(duplicate.bytes_per_copy, string_size(&graph, copies[0]).0);
+        assert_eq!(duplicate.wasted_bytes, 2 * duplicate.bytes_per_copy);
+        assert_eq!(
+            duplicate.top_holders,
+            vec![
+                ("com.example.Model.name".to_string(), 2),
+                ("static com.example.Holder.sName".to_string(), 1),
+            ]
+        );
+    }
+
+    /// Copies sharing their `char[]` only waste their own String, and an
+    /// array also used by another string is not freed by interning.
+    #[test]
+    fn shared_array_duplicates() {
+        let mut dump = DumpBuilder::new();
+        let fields = [
+            ("value", JavaType::Object),
+            ("offset", JavaType::Int),
+            ("count", JavaType::Int),
+        ];
+        let string = dump.class("java.lang.String", 0, &fields);
+        let substring = |dump: &mut DumpBuilder, array, offset, count| {
+            dump.instance(
+                string,
+                &[
+                    ("value", JavaValue::Object(array)),
+                    ("offset", JavaValue::Int(offset)),
+                    ("count", JavaValue::Int(count)),
+                ],
+            )
+        };
+        let shared = dump.primitive_array(JavaType::Char, &chars("hello"));
+        let hello = [
+            substring(&mut dump, shared, 0, 5),
+            substring(&mut dump, shared, 0, 5),
+            substring(&mut dump, shared, 0, 5),
+        ];
+        let own = dump.primitive_array(JavaType::Char, &chars("world"));
+        let world = substring(&mut dump, own, 0, 5);
+        let sentence = dump.primitive_array(JavaType::Char, &chars("hello world"));
+        let world_copy = substring(&mut dump, sentence, 6, 5);
+        substring(&mut dump, sentence, 0, 11);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        assert_eq!(
+            string_size(&graph, world_copy),
+            (graph.shallow_size(world_copy), Some(sentence))
+        );
+
+        let duplicates = find_duplicate_strings(&graph);
+        assert_eq!(duplicates.len(), 2);
+        let own_size = graph.shallow_size(hello[0]);
+        let by_value = |value: &str| duplicates.iter().find(|d| d.value == value).unwrap();
+        let duplicate = by_value("hello");
+        assert_eq!(
+            duplicate.bytes_per_copy,
+            own_size + graph.shallow_size(shared)
+        );
+        assert_eq!(duplicate.wasted_bytes, 2 * own_size);
+        // interning keeps `world` and its array, "hello world" keeps the other array
+        let duplicate = by_value("world");
+        assert_eq!(duplicate.string_ids, vec![world, world_copy]);
+        assert_eq!(duplicate.wasted_bytes, own_size);
+    }
+}
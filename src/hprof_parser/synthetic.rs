@@ -0,0 +1,205 @@
+use crate::hprof_parser::graph::HeapGraph;
+use crate::hprof_parser::snapshot::JavaValue;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyntheticKind {
+    /// `MainActivity$5`, also Kotlin lambdas compiled to classes such as
+    /// `MainActivity$onCreate$1`
+    AnonymousClass,
+    /// `MainActivity$$ExternalSyntheticLambda3` from D8, `-$$Lambda$MainActivity$...`
+    /// from older desugaring or `MainActivity$$Lambda$12` from the JVM
+    Lambda,
+}
+
+/// A compiler generated class and the class whose code defines it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntheticClass {
+    pub kind: SyntheticKind,
+    pub enclosing_class: String,
+}
+
+impl Display for SyntheticClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            SyntheticKind::AnonymousClass => "anonymous class",
+            SyntheticKind::Lambda => "lambda",
+        };
+        write!(f, "{} in {}", kind, self.enclosing_class)
+    }
+}
+
+/// Recognizes anonymous classes and lambdas from their name, `None` for any
+/// other class. Named inner classes like `Outer$Inner` are left alone as
+/// their name already says what they are.
+pub fn synthetic_class(class_name: &str) -> Option<SyntheticClass> {
+    let lambda = |enclosing_class: String| SyntheticClass {
+        kind: SyntheticKind::Lambda,
+        enclosing_class,
+    };
+    if let Some((enclosing, _)) = class_name.split_once("$$ExternalSyntheticLambda") {
+        return Some(lambda(enclosing.to_string()));
+    }
+    // com.foo.-$$Lambda$MainActivity$2ZaJQcVdC3 -> com.foo.MainActivity
+    if let Some((package, rest)) = class_name.split_once("-$$Lambda$") {
+        let (class, _hash) = rest.rsplit_once('$')?;
+        return Some(lambda(format!("{}{}", package, class)));
+    }
+    if let Some((enclosing, _)) = class_name.split_once("$$Lambda") {
+        return Some(lambda(enclosing.to_string()));
+    }
+
+    let (mut enclosing, last) = class_name.rsplit_once('$')?;
+    if last.is_empty() || !last.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // Kotlin names local classes and lambdas after the enclosing method:
+    // MainActivity$onCreate$1, unlike classes, methods start in lower case
+    while let Some((outer, segment)) = enclosing.rsplit_once('$') {
+        if !segment.starts_with(|c: char| c.is_lowercase()) {
+            break;
+        }
+        enclosing = outer;
+    }
+    Some(SyntheticClass {
+        kind: SyntheticKind::AnonymousClass,
+        enclosing_class: enclosing.to_string(),
+    })
+}
+
+/// What a field generated by the compiler holds, `None` for a regular field:
+/// `this$0` is the outer instance of an inner class or lambda, `f$0` a value
+/// captured by a D8 lambda and `$name` a variable captured by Kotlin.
+pub fn captured_field(class_name: &str, field_name: &str) -> Option<&'static str> {
+    if let Some(depth) = field_name.strip_prefix("this$") {
+        if depth.bytes().all(|b| b.is_ascii_digit()) {
+            return Some("captured outer instance");
+        }
+    }
+    synthetic_class(class_name)?;
+    if let Some(index) = field_name.strip_prefix("f$") {
+        if index.bytes().all(|b| b.is_ascii_digit()) {
+            return Some("captured value");
+        }
+    }
+    if field_name.starts_with('$') && field_name.len() > 1 {
+        return Some("captured variable");
+    }
+    None
+}
+
+/// Non null objects held by the [`captured_field`]s of an instance.
+pub fn captured_objects(graph: &HeapGraph, id: u64) -> Vec<u64> {
+    graph
+        .instance_fields(id)
+        .into_iter()
+        .filter_map(|field| match field.value {
+            JavaValue::Object(value) if value != 0 => {
+                let class_name = graph.class_name(field.class_id)?;
+                captured_field(class_name, field.name).map(|_| value)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// `com.foo.MainActivity$5 (anonymous class in com.foo.MainActivity)`, or
+/// the name unchanged if the class is not synthetic.
+pub fn describe_class(class_name: &str) -> String {
+    match synthetic_class(class_name) {
+        Some(synthetic) => format!("{} ({})", class_name, synthetic),
+        None => class_name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lambda(enclosing_class: &str) -> Option<SyntheticClass> {
+        Some(SyntheticClass {
+            kind: SyntheticKind::Lambda,
+            enclosing_class: enclosing_class.to_string(),
+        })
+    }
+
+    fn anonymous(enclosing_class: &str) -> Option<SyntheticClass> {
+        Some(SyntheticClass {
+            kind: SyntheticKind::AnonymousClass,
+            enclosing_class: enclosing_class.to_string(),
+        })
+    }
+
+    #[test]
+    fn lambdas() {
+        assert_eq!(
+            synthetic_class("com.foo.MainActivity$$ExternalSyntheticLambda3"),
+            lambda("com.foo.MainActivity")
+        );
+        assert_eq!(
+            synthetic_class("com.foo.-$$Lambda$MainActivity$2ZaJQcVdC3"),
+            lambda("com.foo.MainActivity")
+        );
+        assert_eq!(
+            synthetic_class("com.foo.MainActivity$$Lambda$12/0x0000000800c03000"),
+            lambda("com.foo.MainActivity")
+        );
+    }
+
+    #[test]
+    fn anonymous_classes() {
+        assert_eq!(
+            synthetic_class("com.foo.MainActivity$5"),
+            anonymous("com.foo.MainActivity")
+        );
+        assert_eq!(
+            synthetic_class("com.foo.Outer$Inner$1"),
+            anonymous("com.foo.Outer$Inner")
+        );
+        // Kotlin local classes and lambdas are named after their method
+        assert_eq!(
+            synthetic_class("com.foo.MainActivity$onCreate$1"),
+            anonymous("com.foo.MainActivity")
+        );
+        assert_eq!(
+            synthetic_class("com.foo.MainActivity$onCreate$listener$2"),
+            anonymous("com.foo.MainActivity")
+        );
+    }
+
+    #[test]
+    fn other_classes() {
+        assert_eq!(synthetic_class("com.foo.MainActivity"), None);
+        assert_eq!(synthetic_class("com.foo.Outer$Inner"), None);
+        assert_eq!(synthetic_class("com.foo.Outer$"), None);
+        assert_eq!(synthetic_class("com.foo.Outer$1a"), None);
+    }
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(
+            describe_class("com.foo.MainActivity$5"),
+            "com.foo.MainActivity$5 (anonymous class in com.foo.MainActivity)"
+        );
+        assert_eq!(describe_class("com.foo.Model"), "com.foo.Model");
+    }
+
+    #[test]
+    fn captured_fields() {
+        assert_eq!(
+            captured_field("com.foo.Outer$Inner", "this$0"),
+            Some("captured outer instance")
+        );
+        assert_eq!(
+            captured_field("com.foo.A$$ExternalSyntheticLambda0", "f$1"),
+            Some("captured value")
+        );
+        assert_eq!(
+            captured_field("com.foo.A$onCreate$1", "$adapter"),
+            Some("captured variable")
+        );
+        // only synthetic classes capture values
+        assert_eq!(captured_field("com.foo.Model", "f$1"), None);
+        assert_eq!(captured_field("com.foo.A$1", "name"), None);
+    }
+}
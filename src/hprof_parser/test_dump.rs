@@ -0,0 +1,291 @@
+use crate::hprof_parser::snapshot::{JavaType, JavaValue};
+use std::collections::HashMap;
+
+/// Writes small hprof files for unit tests, with 4 byte ids and the header
+/// of an ART dump.
+pub(crate) struct DumpBuilder {
+    next_id: u64,
+    next_serial: u32,
+    strings: HashMap<String, u64>,
+    records: Vec<u8>,
+    heap: Vec<u8>,
+    /// class id -> superclass id and instance fields
+    classes: HashMap<u64, (u64, Vec<(String, JavaType)>)>,
+    class_serials: HashMap<u64, u32>,
+    /// stack trace serial number of the objects written next
+    allocation_trace: u32,
+}
+
+impl DumpBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_id: 0x1000,
+            next_serial: 1,
+            strings: HashMap::new(),
+            records: Vec::new(),
+            heap: Vec::new(),
+            classes: HashMap::new(),
+            class_serials: HashMap::new(),
+            allocation_trace: 0,
+        }
+    }
+
+    /// An id no object has yet, e.g. to reference an object written later.
+    pub(crate) fn new_id(&mut self) -> u64 {
+        self.next_id += 8;
+        self.next_id
+    }
+
+    fn string(&mut self, text: &str) -> u64 {
+        if let Some(id) = self.strings.get(text) {
+            return *id;
+        }
+        let id = self.new_id();
+        self.strings.insert(text.to_string(), id);
+        let mut body = id_bytes(id);
+        body.extend_from_slice(text.as_bytes());
+        self.record(0x01, &body);
+        id
+    }
+
+    fn record(&mut self, tag: u8, body: &[u8]) {
+        self.records.push(tag);
+        self.records.extend_from_slice(&0u32.to_be_bytes());
+        self.records
+            .extend_from_slice(&(body.len() as u32).to_be_bytes());
+        self.records.extend_from_slice(body);
+    }
+
+    /// A class with instance fields, `super_id` 0 for `java.lang.Object`.
+    pub(crate) fn class(&mut self, name: &str, super_id: u64, fields: &[(&str, JavaType)]) -> u64 {
+        self.class_with_statics(name, super_id, fields, &[])
+    }
+
+    pub(crate) fn class_with_statics(
+        &mut self,
+        name: &str,
+        super_id: u64,
+        fields: &[(&str, JavaType)],
+        statics: &[(&str, JavaValue)],
+    ) -> u64 {
+        let id = self.new_id();
+        let serial = self.next_serial;
+        self.next_serial += 1;
+        let name_id = self.string(name);
+        let mut load = serial.to_be_bytes().to_vec();
+        load.extend(id_bytes(id));
+        load.extend_from_slice(&0u32.to_be_bytes());
+        load.extend(id_bytes(name_id));
+        self.record(0x02, &load);
+        self.class_serials.insert(id, serial);
+
+        let mut dump = vec![0x20];
+        dump.extend(id_bytes(id));
+        dump.extend_from_slice(&0u32.to_be_bytes());
+        dump.extend(id_bytes(super_id));
+        // loader, signers, protection domain and 2 reserved ids
+        dump.extend(std::iter::repeat_n(0, 5 * 4));
+        dump.extend_from_slice(&0u32.to_be_bytes());
+        // no constant
+        dump.extend_from_slice(&[0, 0]);
+        dump.extend_from_slice(&(statics.len() as u16).to_be_bytes());
+        for (field, value) in statics {
+            dump.extend(id_bytes(self.string(field)));
+            dump.push(value.java_type() as u8);
+            dump.extend(value_bytes(*value));
+        }
+        dump.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+        for (field, java_type) in fields {
+            dump.extend(id_bytes(self.string(field)));
+            dump.push(*java_type as u8);
+        }
+        self.heap.extend(dump);
+        self.classes.insert(
+            id,
+            (
+                super_id,
+                fields
+                    .iter()
+                    .map(|(name, java_type)| (name.to_string(), *java_type))
+                    .collect(),
+            ),
+        );
+        id
+    }
+
+    /// Fields missing from `values` are left to 0.
+    pub(crate) fn instance(&mut self, class_id: u64, values: &[(&str, JavaValue)]) -> u64 {
+        let id = self.new_id();
+        self.instance_with_id(id, class_id, values);
+        id
+    }
+
+    pub(crate) fn instance_with_id(
+        &mut self,
+        id: u64,
+        class_id: u64,
+        values: &[(&str, JavaValue)],
+    ) {
+        let mut fields = Vec::new();
+        let mut class = class_id;
+        while let Some((super_id, class_fields)) = self.classes.get(&class) {
+            for (name, java_type) in class_fields {
+                match values.iter().find(|(field, _)| field == name) {
+                    Some((_, value)) => fields.extend(value_bytes(*value)),
+                    None => fields.extend(std::iter::repeat_n(0, type_size(*java_type))),
+                }
+            }
+            class = *super_id;
+        }
+        self.heap.push(0x21);
+        self.heap.extend(id_bytes(id));
+        self.heap
+            .extend_from_slice(&self.allocation_trace.to_be_bytes());
+        self.heap.extend(id_bytes(class_id));
+        self.heap
+            .extend_from_slice(&(fields.len() as u32).to_be_bytes());
+        self.heap.extend(fields);
+    }
+
+    pub(crate) fn primitive_array(&mut self, element_type: JavaType, values: &[JavaValue]) -> u64 {
+        let id = self.new_id();
+        self.heap.push(0x23);
+        self.heap.extend(id_bytes(id));
+        self.heap
+            .extend_from_slice(&self.allocation_trace.to_be_bytes());
+        self.heap
+            .extend_from_slice(&(values.len() as u32).to_be_bytes());
+        self.heap.push(element_type as u8);
+        for value in values {
+            self.heap.extend(value_bytes(*value));
+        }
+        id
+    }
+
+    pub(crate) fn object_array(&mut self, class_id: u64, elements: &[u64]) -> u64 {
+        let id = self.new_id();
+        self.heap.push(0x22);
+        self.heap.extend(id_bytes(id));
+        self.heap
+            .extend_from_slice(&self.allocation_trace.to_be_bytes());
+        self.heap
+            .extend_from_slice(&(elements.len() as u32).to_be_bytes());
+        self.heap.extend(id_bytes(class_id));
+        for element in elements {
+            self.heap.extend(id_bytes(*element));
+        }
+        id
+    }
+
+    /// A STACK_FRAME record of a method of `class_id`.
+    pub(crate) fn stack_frame(
+        &mut self,
+        class_id: u64,
+        method: &str,
+        source_file: &str,
+        line: i32,
+    ) -> u64 {
+        let id = self.new_id();
+        let mut body = id_bytes(id);
+        for text in [method, "()V", source_file] {
+            body.extend(id_bytes(self.string(text)));
+        }
+        body.extend_from_slice(&self.class_serials[&class_id].to_be_bytes());
+        body.extend_from_slice(&line.to_be_bytes());
+        self.record(0x04, &body);
+        id
+    }
+
+    /// A STACK_TRACE record, innermost frame first. Returns its serial number.
+    pub(crate) fn stack_trace(&mut self, thread_serial: u32, frame_ids: &[u64]) -> u32 {
+        let serial = self.next_serial;
+        self.next_serial += 1;
+        let mut body = serial.to_be_bytes().to_vec();
+        body.extend_from_slice(&thread_serial.to_be_bytes());
+        body.extend_from_slice(&(frame_ids.len() as u32).to_be_bytes());
+        for id in frame_ids {
+            body.extend(id_bytes(*id));
+        }
+        self.record(0x05, &body);
+        serial
+    }
+
+    /// Objects written from now on are allocated from the stack trace
+    /// `serial`, 0 for none.
+    pub(crate) fn allocated_at(&mut self, serial: u32) {
+        self.allocation_trace = serial;
+    }
+
+    /// A ROOT_THREAD_OBJECT gc root.
+    pub(crate) fn thread_root(&mut self, id: u64, thread_serial: u32, trace_serial: u32) {
+        self.heap.push(0x08);
+        self.heap.extend(id_bytes(id));
+        self.heap.extend_from_slice(&thread_serial.to_be_bytes());
+        self.heap.extend_from_slice(&trace_serial.to_be_bytes());
+    }
+
+    /// A ROOT_JAVA_FRAME gc root, or ROOT_JNI_LOCAL with `jni`.
+    pub(crate) fn local_root(&mut self, id: u64, thread_serial: u32, frame: i32, jni: bool) {
+        self.heap.push(if jni { 0x02 } else { 0x03 });
+        self.heap.extend(id_bytes(id));
+        self.heap.extend_from_slice(&thread_serial.to_be_bytes());
+        self.heap.extend_from_slice(&frame.to_be_bytes());
+    }
+
+    /// A ROOT_STICKY_CLASS gc root, what keeps the statics of a class alive.
+    pub(crate) fn sticky_class(&mut self, class_id: u64) {
+        self.heap.push(0x05);
+        self.heap.extend(id_bytes(class_id));
+    }
+
+    /// A ROOT_FINALIZING gc root, an object waiting for its finalizer to run.
+    pub(crate) fn finalizing_root(&mut self, id: u64) {
+        self.heap.push(0x8A);
+        self.heap.extend(id_bytes(id));
+    }
+
+    /// A ROOT_UNKNOWN gc root.
+    pub(crate) fn root(&mut self, id: u64) {
+        self.heap.push(0xFF);
+        self.heap.extend(id_bytes(id));
+    }
+
+    pub(crate) fn build(mut self) -> Vec<u8> {
+        let heap = std::mem::take(&mut self.heap);
+        self.record(0x1C, &heap);
+        self.record(0x2C, &[]);
+        let mut bytes = b"JAVA PROFILE 1.0.3\0".to_vec();
+        bytes.extend_from_slice(&4u32.to_be_bytes());
+        bytes.extend_from_slice(&0u64.to_be_bytes());
+        bytes.extend(self.records);
+        bytes
+    }
+}
+
+fn id_bytes(id: u64) -> Vec<u8> {
+    (id as u32).to_be_bytes().to_vec()
+}
+
+fn type_size(java_type: JavaType) -> usize {
+    match java_type {
+        JavaType::Object => 4,
+        JavaType::Boolean | JavaType::Byte => 1,
+        JavaType::Char | JavaType::Short => 2,
+        JavaType::Float | JavaType::Int => 4,
+        JavaType::Double | JavaType::Long => 8,
+    }
+}
+
+fn value_bytes(value: JavaValue) -> Vec<u8> {
+    match value {
+        JavaValue::Object(id) => id_bytes(id),
+        JavaValue::Boolean(value) => vec![value as u8],
+        JavaValue::Char(value) => value.to_be_bytes().to_vec(),
+        JavaValue::Float(value) => value.to_be_bytes().to_vec(),
+        JavaValue::Double(value) => value.to_be_bytes().to_vec(),
+        JavaValue::Byte(value) => value.to_be_bytes().to_vec(),
+        JavaValue::Short(value) => value.to_be_bytes().to_vec(),
+        JavaValue::Int(value) => value.to_be_bytes().to_vec(),
+        JavaValue::Long(value) => value.to_be_bytes().to_vec(),
+    }
+}
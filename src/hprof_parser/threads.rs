@@ -0,0 +1,307 @@
+use crate::hprof_parser::graph::{HeapGraph, StackFrame};
+use crate::hprof_parser::path::describe_object;
+use crate::hprof_parser::snapshot::{JavaValue, SubTag};
+use crate::hprof_parser::strings::string_value;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// An object held by a local variable, the kind of root tells whether the
+/// frame is Java or native code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LocalRoot {
+    pub object_id: u64,
+    /// `true` for a RootJniLocal, `false` for a RootJavaFrame
+    pub jni: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadFrame<'a> {
+    /// `None` if the dump has no STACK_FRAME record for it
+    pub frame: Option<StackFrame<'a>>,
+    pub locals: Vec<LocalRoot>,
+}
+
+/// A thread as found in the dump: its `java.lang.Thread` object, its stack
+/// and the objects its frames hold.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo<'a> {
+    pub object_id: u64,
+    pub thread_serial_number: u32,
+    pub name: Option<String>,
+    pub daemon: Option<bool>,
+    pub priority: Option<i32>,
+    /// innermost frame first
+    pub frames: Vec<ThreadFrame<'a>>,
+    /// locals whose frame number is not part of the stack trace, as ART
+    /// dumps them without any trace
+    pub unattributed_locals: Vec<LocalRoot>,
+}
+
+impl<'a> ThreadInfo<'a> {
+    pub fn display<'t>(&'t self, graph: &'t HeapGraph<'a>) -> ThreadDisplay<'t, 'a> {
+        ThreadDisplay {
+            thread: self,
+            graph,
+        }
+    }
+}
+
+/// Every thread with a RootThreadObject, sorted by thread serial number.
+pub fn threads<'a>(graph: &HeapGraph<'a>) -> Vec<ThreadInfo<'a>> {
+    let mut locals: HashMap<u32, Vec<(i32, LocalRoot)>> = HashMap::new();
+    for root in graph.roots() {
+        let (object, jni) = match root {
+            SubTag::RootJavaFrame(object) => (object, false),
+            SubTag::RootJniLocal(object) => (object, true),
+            _ => continue,
+        };
+        locals
+            .entry(object.thread_serial_number)
+            .or_default()
+            .push((
+                object.frame_number_in_stack_trace,
+                LocalRoot {
+                    object_id: object.object_id,
+                    jni,
+                },
+            ));
+    }
+
+    let mut threads: Vec<ThreadInfo<'a>> = graph
+        .roots()
+        .iter()
+        .filter_map(|root| match root {
+            SubTag::RootThreadObject(object) => Some(object),
+            _ => None,
+        })
+        .map(|object| {
+            // the third field of a thread root is its stack trace serial number
+            let trace = graph.stack_trace(object.frame_number_in_stack_trace as u32);
+            let mut frames: Vec<ThreadFrame<'a>> = trace
+                .map(|trace| trace.frame_ids.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(|id| ThreadFrame {
+                    frame: graph.stack_frame(*id).copied(),
+                    locals: Vec::new(),
+                })
+                .collect();
+
+            let mut unattributed_locals = Vec::new();
+            for (frame_number, local) in locals
+                .remove(&object.thread_serial_number)
+                .unwrap_or_default()
+            {
+                match usize::try_from(frame_number)
+                    .ok()
+                    .and_then(|index| frames.get_mut(index))
+                {
+                    Some(frame) => frame.locals.push(local),
+                    None => unattributed_locals.push(local),
+                }
+            }
+
+            let thread = object.object_id;
+            ThreadInfo {
+                object_id: thread,
+                thread_serial_number: object.thread_serial_number,
+                name: match graph.field(thread, "name") {
+                    Some(JavaValue::Object(name)) => {
+                        string_value(graph, name).map(|name| name.into_owned())
+                    }
+                    _ => None,
+                },
+                daemon: match thread_field(graph, thread, "daemon") {
+                    Some(JavaValue::Boolean(daemon)) => Some(daemon),
+                    _ => None,
+                },
+                priority: match thread_field(graph, thread, "priority") {
+                    Some(JavaValue::Int(priority)) => Some(priority),
+                    _ => None,
+                },
+                frames,
+                unattributed_locals,
+            }
+        })
+        .collect();
+    threads.sort_by_key(|thread| thread.thread_serial_number);
+    threads
+}
+
+/// Field of a Thread, or of its `holder` since JDK 19.
+fn thread_field(graph: &HeapGraph, thread: u64, name: &str) -> Option<JavaValue> {
+    graph
+        .field(thread, name)
+        .or_else(|| match graph.field(thread, "holder") {
+            Some(JavaValue::Object(holder)) => graph.field(holder, name),
+            _ => None,
+        })
+}
+
+/// `com.foo.Bar.run(Bar.java:42)`, in the format of `Throwable.printStackTrace`.
+pub fn describe_frame(graph: &HeapGraph, frame: &StackFrame) -> String {
+    let class_name = frame
+        .class_id
+        .and_then(|class_id| graph.class_name(class_id))
+        .unwrap_or("<unknown class>");
+    let location = match frame.line_number {
+        line if line > 0 => format!("{}:{}", frame.source_file, line),
+        -2 => "Compiled method".to_string(),
+        -3 => "Native Method".to_string(),
+        _ if !frame.source_file.is_empty() => frame.source_file.to_string(),
+        _ => "Unknown Source".to_string(),
+    };
+    format!("{}.{}({})", class_name, frame.method_name, location)
+}
+
+pub struct ThreadDisplay<'t, 'a> {
+    thread: &'t ThreadInfo<'a>,
+    graph: &'t HeapGraph<'a>,
+}
+
+impl<'t, 'a> Display for ThreadDisplay<'t, 'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let thread = self.thread;
+        write!(f, "\"{}\"", thread.name.as_deref().unwrap_or("<unnamed>"))?;
+        if thread.daemon == Some(true) {
+            write!(f, " daemon")?;
+        }
+        if let Some(priority) = thread.priority {
+            write!(f, " prio={}", priority)?;
+        }
+        writeln!(
+            f,
+            " tid={} ({:#x})",
+            thread.thread_serial_number, thread.object_id
+        )?;
+
+        let write_locals = |f: &mut Formatter<'_>, locals: &[LocalRoot]| {
+            for local in locals {
+                writeln!(
+                    f,
+                    "      - {} {} ({:#x})",
+                    if local.jni { "jni local" } else { "local" },
+                    describe_object(self.graph, local.object_id),
+                    local.object_id
+                )?;
+            }
+            Ok(())
+        };
+        for frame in &thread.frames {
+            match &frame.frame {
+                Some(stack_frame) => {
+                    writeln!(f, "    at {}", describe_frame(self.graph, stack_frame))?
+                }
+                None => writeln!(f, "    at <missing frame>")?,
+            }
+            write_locals(f, &frame.locals)?;
+        }
+        if !thread.unattributed_locals.is_empty() {
+            writeln!(f, "    locals without frame:")?;
+            write_locals(f, &thread.unattributed_locals)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hprof_parser::snapshot::JavaType;
+    use crate::hprof_parser::test_dump::DumpBuilder;
+
+    #[test]
+    fn frames_and_locals() {
+        let mut dump = DumpBuilder::new();
+        let string_class = dump.class(
+            "java.lang.String",
+            0,
+            &[("count", JavaType::Int), ("value", JavaType::Object)],
+        );
+        let thread_class = dump.class(
+            "java.lang.Thread",
+            0,
+            &[
+                ("name", JavaType::Object),
+                ("daemon", JavaType::Boolean),
+                ("priority", JavaType::Int),
+            ],
+        );
+        let worker_class = dump.class("com.example.Worker", 0, &[]);
+        let value: Vec<JavaValue> = b"main".iter().map(|b| JavaValue::Byte(*b as i8)).collect();
+        let value = dump.primitive_array(JavaType::Byte, &value);
+        let name = dump.instance(
+            string_class,
+            &[
+                ("count", JavaValue::Int(4 << 1)),
+                ("value", JavaValue::Object(value)),
+            ],
+        );
+        let main = dump.instance(
+            thread_class,
+            &[
+                ("name", JavaValue::Object(name)),
+                ("priority", JavaValue::Int(5)),
+            ],
+        );
+        let daemon = dump.instance(
+            thread_class,
+            &[
+                ("daemon", JavaValue::Boolean(true)),
+                ("priority", JavaValue::Int(10)),
+            ],
+        );
+        let locals: Vec<u64> = (0..4).map(|_| dump.instance(worker_class, &[])).collect();
+
+        let run = dump.stack_frame(worker_class, "run", "Worker.java", 42);
+        let poll = dump.stack_frame(worker_class, "poll", "Worker.java", -3);
+        let trace = dump.stack_trace(1, &[poll, run]);
+        // listed out of order, threads are sorted by serial number
+        dump.thread_root(daemon, 2, 0);
+        dump.thread_root(main, 1, trace);
+        dump.local_root(locals[0], 1, 0, true);
+        dump.local_root(locals[1], 1, 1, false);
+        dump.local_root(locals[2], 1, 7, false);
+        dump.local_root(locals[3], 2, 0, false);
+        let bytes = dump.build();
+        let graph = HeapGraph::from_bytes(&bytes).unwrap();
+
+        let threads = threads(&graph);
+        assert_eq!(threads.len(), 2);
+        let thread = &threads[0];
+        assert_eq!((thread.object_id, thread.thread_serial_number), (main, 1));
+        assert_eq!(thread.name.as_deref(), Some("main"));
+        assert_eq!(thread.daemon, Some(false));
+        assert_eq!(thread.priority, Some(5));
+        let frames: Vec<String> = thread
+            .frames
+            .iter()
+            .map(|frame| describe_frame(&graph, frame.frame.as_ref().unwrap()))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                "com.example.Worker.poll(Native Method)",
+                "com.example.Worker.run(Worker.java:42)",
+            ]
+        );
+        let local = |id: u64, jni: bool| LocalRoot { object_id: id, jni };
+        assert_eq!(thread.frames[0].locals, vec![local(locals[0], true)]);
+        assert_eq!(thread.frames[1].locals, vec![local(locals[1], false)]);
+        assert_eq!(thread.unattributed_locals, vec![local(locals[2], false)]);
+
+        let thread = &threads[1];
+        assert_eq!((thread.object_id, thread.thread_serial_number), (daemon, 2));
+        assert_eq!(thread.name, None);
+        assert_eq!(thread.daemon, Some(true));
+        assert!(thread.frames.is_empty());
+        assert_eq!(thread.unattributed_locals, vec![local(locals[3], false)]);
+
+        let text = threads[0].display(&graph).to_string();
+        assert!(text.starts_with(&format!("\"main\" prio=5 tid=1 ({:#x})\n", main)));
+        assert!(text.contains("    at com.example.Worker.poll(Native Method)\n      - jni local com.example.Worker instance"));
+        assert!(text.contains("    locals without frame:\n"));
+        let text = threads[1].display(&graph).to_string();
+        assert!(text.starts_with("\"<unnamed>\" daemon prio=10 tid=2"));
+    }
+}